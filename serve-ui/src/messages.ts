@@ -6,6 +6,7 @@ import * as Either from "effect/Either";
 
 const AlertMessage = S.struct({
     file: S.union(S.string, S.null),
+    line: S.union(S.number, S.null),
     message: S.string,
     context: S.array(S.string),
 });
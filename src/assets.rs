@@ -4,7 +4,7 @@
 //! Merging and emitting the "assets" used in the pass-2 Tectonic build.
 
 use sha2::Digest;
-use std::fs::File;
+use std::{fs::File, time::SystemTime};
 use tectonic::{
     config::PersistentConfig,
     driver::{OutputFormat, PassSetting, ProcessingSessionBuilder},
@@ -20,9 +20,39 @@ use tectonic_status_base::{ChatterLevel, StatusBackend};
 use crate::{
     cache::{Cache, OpCacheData},
     index::IndexCollection,
-    operation::{DigestComputer, OpOutputStream, RuntimeEntity, RuntimeEntityIdent},
+    operation::{DigestComputer, DigestData, OpOutputStream, RuntimeEntity, RuntimeEntityIdent},
+    tex_pass::{add_tex_session_inputs, tex_build_date, TEX_FORMAT_NAME},
 };
 
+/// Compute the identifier of the asset merge operation, given the
+/// identifiers of the per-input asset files.
+pub fn merge_operation_id(
+    asset_ids: &[RuntimeEntityIdent],
+    indices: &IndexCollection,
+) -> DigestData {
+    let mut dc = DigestComputer::default();
+    dc.update("merge_assets_v1");
+
+    for input in asset_ids {
+        input.update_digest(&mut dc, indices);
+    }
+
+    dc.finalize()
+}
+
+/// Get the identifier of the merged asset file.
+pub fn merged_assets_ident(indices: &mut IndexCollection) -> RuntimeEntityIdent {
+    RuntimeEntityIdent::new_other_file("cache/assets.json", indices)
+}
+
+/// Compute the identifier of the asset emission operation.
+pub fn emit_operation_id(asset_file: RuntimeEntityIdent, indices: &IndexCollection) -> DigestData {
+    let mut dc = DigestComputer::default();
+    dc.update("emit_assets_v1");
+    asset_file.update_digest(&mut dc, indices);
+    dc.finalize()
+}
+
 pub fn maybe_asset_merge_operation(
     indices: &mut IndexCollection,
     asset_ids: &[RuntimeEntityIdent],
@@ -34,23 +64,15 @@ pub fn maybe_asset_merge_operation(
     // to rerun the op, and a simple check that all of the old inputs are
     // unchanged won't catch that.
 
-    let mut dc = DigestComputer::default();
-    dc.update("merge_assets_v1");
-
-    for input in asset_ids {
-        input.update_digest(&mut dc, indices);
-    }
-
-    let opid = dc.finalize();
+    let opid = merge_operation_id(asset_ids, indices);
+    let output = merged_assets_ident(indices);
 
-    let output = RuntimeEntityIdent::new_other_file("cache/assets.json", indices);
-
-    let needs_rerun = atry!(
-        cache.operation_needs_rerun(&opid, indices, status);
+    let rerun_reason = atry!(
+        cache.operation_needs_rerun(&opid, "the asset merge", indices, status);
         ["failed to probe cache for asset merge operation"]
     );
 
-    if !needs_rerun {
+    if rerun_reason.is_none() {
         return Ok(output);
     }
 
@@ -121,18 +143,14 @@ pub fn maybe_emit_assets_operation(
 ) -> Result<Vec<RuntimeEntity>> {
     // Set up the information about the operation.
 
-    let mut dc = DigestComputer::default();
-    dc.update("emit_assets_v1");
-    asset_file.update_digest(&mut dc, indices);
+    let opid = emit_operation_id(asset_file, indices);
 
-    let opid = dc.finalize();
-
-    let needs_rerun = atry!(
-        cache.operation_needs_rerun(&opid, indices, status);
+    let rerun_reason = atry!(
+        cache.operation_needs_rerun(&opid, "the asset emission", indices, status);
         ["failed to probe cache for asset merge operation"]
     );
 
-    if !needs_rerun {
+    if rerun_reason.is_none() {
         // If we're not rerunning the operation, nothing should have changed!
         return Ok(Vec::new());
     }
@@ -141,6 +159,7 @@ pub fn maybe_emit_assets_operation(
 
     let mut ocd = OpCacheData::new(opid);
     ocd.add_input(asset_file);
+    add_tex_session_inputs(&mut ocd, indices);
 
     let assets_path = indices.path_for_runtime_ident(asset_file).unwrap();
 
@@ -165,8 +184,10 @@ pub fn maybe_emit_assets_operation(
         outputs.push(cache.unconditional_entity(ident, indices)?);
     }
 
+    let build_date = tex_build_date()?;
+
     atry!(
-        emit_assets(assets, status).map_err(SyncError::new);
+        emit_assets(assets, build_date, status).map_err(SyncError::new);
         ["failed to emit Tectonic HTML assets"]
     );
 
@@ -180,7 +201,11 @@ pub fn maybe_emit_assets_operation(
     Ok(outputs)
 }
 
-fn emit_assets(assets: AssetSpecification, status: &mut dyn StatusBackend) -> Result<(), OldError> {
+fn emit_assets(
+    assets: AssetSpecification,
+    build_date: SystemTime,
+    status: &mut dyn StatusBackend,
+) -> Result<(), OldError> {
     // Suboptimal: this is basically copy-paste from the pass2 code.
     let config = PersistentConfig::open(false)?;
     let bundle = config.default_bundle(false, status)?;
@@ -208,9 +233,9 @@ fn emit_assets(assets: AssetSpecification, status: &mut dyn StatusBackend) -> Re
     let mut sess = ProcessingSessionBuilder::new_with_security(security);
     sess.primary_input_buffer(input.as_bytes())
         .tex_input_name("texput")
-        .build_date(std::time::SystemTime::now())
+        .build_date(build_date)
         .bundle(bundle)
-        .format_name("latex")
+        .format_name(TEX_FORMAT_NAME)
         .output_format(OutputFormat::Html)
         .html_precomputed_assets(assets)
         .filesystem_root(&root)
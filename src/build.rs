@@ -5,31 +5,53 @@
 
 use clap::Args;
 use std::{
+    collections::HashSet,
     sync::{Arc, Mutex},
     time::Instant,
 };
 use tectonic_errors::prelude::*;
-use tectonic_status_base::StatusBackend;
+use tectonic_status_base::{tt_note, StatusBackend};
 use tokio::task::spawn_blocking;
 
 use crate::{
-    assets, cache, entrypoint_file, index, inputs,
+    assets, cache, entrypoint_file,
+    failures::{FailureLog, Phase, RecordingBus},
+    index, index_export, index_pages, inputs,
     messages::{
         new_sync_bus_channel, BuildCompleteMessage, CliStatusMessageBus, Message, MessageBus,
     },
     operation::{RuntimeEntity, RuntimeEntityIdent},
-    pass1, pass2, tex_pass, yarn,
+    pass1, pass2, redirects, tag_pages, tex_pass, yarn, InputId,
 };
 
 /// The return value is potentially a list of the final outputs that were
 /// modified during this build process, if the boolean argument is true. The
 /// list may be empty if nothing actually changed, or if the argument is false.
 /// This list is used in "serve" mode to efficiently update Parcel.js.
+///
+/// If *only* is specified, the second pass is only run on the inputs that it
+/// selects, and only the tag and index pages affected by those inputs are
+/// emitted. The first pass always considers all of the inputs, since they're
+/// all needed to resolve the index references.
+///
+/// Inputs that fail are recorded in *failures*. In "keep going" mode, they're
+/// left out of the rest of the build, and the stale outputs aren't cleaned up,
+/// since we can't know which of them the failed inputs would have created.
+///
+/// Once they've been loaded, the indices and build cache are kept in *state*,
+/// even if the build fails, so that the caller can save what the cache has
+/// learned.
 async fn primary_build_implementation<T: MessageBus + 'static>(
     n_workers: usize,
     collect_paths: bool,
-    mut bus: T,
+    explain: bool,
+    only: Option<inputs::InputFilter>,
+    failures: FailureLog,
+    state: &mut Option<(index::IndexCollection, cache::Cache)>,
+    bus: T,
 ) -> Result<Vec<String>> {
+    let mut bus = RecordingBus::new(bus, failures.clone());
+
     // Set up data structures. Here the return type of spawn_blocking is a
     // Result<Result<IndexCollection>, JoinError>, so we have to double-unwrap
     // it.
@@ -37,6 +59,10 @@ async fn primary_build_implementation<T: MessageBus + 'static>(
     let (mut bus_tx, bus_rx) = new_sync_bus_channel();
 
     let handle = spawn_blocking(move || -> Result<(index::IndexCollection, cache::Cache, Vec<RuntimeEntityIdent>)> {
+        // Check this before any of the TeX workers need it, so that a bad
+        // value is reported once.
+        tex_pass::tex_build_date()?;
+
         bus_tx.post(Message::PhaseStarted("load-indices".into()));
 
         let mut indices = index::IndexCollection::new()?;
@@ -47,10 +73,11 @@ async fn primary_build_implementation<T: MessageBus + 'static>(
 
         bus_tx.post(Message::PhaseStarted("load-cache".into()));
 
-        let cache = atry!(
+        let mut cache = atry!(
             cache::Cache::new(&mut indices, &mut bus_tx);
             ["error initializing build cache"]
         );
+        cache.set_explain(explain);
 
         bus_tx.post(Message::PhaseStarted("collect-inputs".into()));
 
@@ -66,33 +93,63 @@ async fn primary_build_implementation<T: MessageBus + 'static>(
     });
 
     bus_rx.drain(bus.clone()).await;
-    let (mut indices, mut cache, inputs) = handle.await??;
+    let (indices, cache, inputs) = handle.await??;
+    let (indices, cache) = state.insert((indices, cache));
+
+    let selected: Option<Vec<RuntimeEntityIdent>> = match &only {
+        Some(filter) => {
+            let selected: Vec<_> = inputs
+                .iter()
+                .filter(|i| filter.matches(indices.relpath_for_tex_source(**i).unwrap()))
+                .copied()
+                .collect();
+
+            ensure!(
+                !selected.is_empty(),
+                "no inputs match the `--only` selection"
+            );
+            Some(selected)
+        }
+
+        None => None,
+    };
 
     // First TeX pass of indexing and gathering font/asset information.
 
     bus.post(Message::PhaseStarted("pass-1".into())).await;
+    failures.set_phase(Phase::Pass1);
 
     let mut p1r = pass1::Pass1Processor::default();
     let _n_processed = tex_pass::process_inputs(
         &inputs,
         n_workers,
         &mut p1r,
-        &mut cache,
-        &mut indices,
+        cache,
+        indices,
+        &failures,
         bus.clone(),
     )
     .await?;
 
+    failures.set_phase(Phase::Indexing);
+    let index_failures = failures.clone();
+
     let (mut bus_tx, bus_rx) = new_sync_bus_channel();
+    let (mut indices, mut cache) = state.take().unwrap();
 
-    let handle = spawn_blocking(
-        #[allow(clippy::type_complexity)]
-        move || -> Result<(Vec<RuntimeEntityIdent>, RuntimeEntityIdent, Vec<RuntimeEntity>, index::IndexCollection, cache::Cache)> {
+    let handle = spawn_blocking(move || {
+        let result = (|| -> Result<(RuntimeEntityIdent, Vec<RuntimeEntity>)> {
             let (asset_ids, metadata_ids) = p1r.unpack();
 
             // Resolve cross-references and validate.
 
-            index::construct_indices(&mut indices, &metadata_ids[..], &mut cache, &mut bus_tx)?;
+            index::construct_indices(
+                &mut indices,
+                &metadata_ids[..],
+                &mut cache,
+                &index_failures,
+                &mut bus_tx,
+            )?;
 
             // Generate the merged asset info and emit the files. Start collecting
             // information about our outputs that will feed into the Parcel.js build
@@ -113,25 +170,39 @@ async fn primary_build_implementation<T: MessageBus + 'static>(
                 &mut bus_tx,
             )?;
 
-            Ok((metadata_ids, merged_assets_id, maybe_modified_output_files, indices, cache))
-        },
-    );
+            Ok((merged_assets_id, maybe_modified_output_files))
+        })();
+
+        (indices, cache, result)
+    });
 
     bus_rx.drain(bus.clone()).await;
-    let (metadata_ids, merged_assets_id, mut maybe_modified_output_files, mut indices, mut cache) =
-        handle.await??;
+    let (indices, cache, result) = handle.await?;
+    let (indices, cache) = state.insert((indices, cache));
+    let (merged_assets_id, mut maybe_modified_output_files) = result?;
 
-    // TeX pass 2, emitting
+    // TeX pass 2, emitting. Inputs that have already failed are skipped.
 
     bus.post(Message::PhaseStarted("pass-2".into())).await;
-
-    let mut p2r = pass2::Pass2Processor::new(metadata_ids, merged_assets_id, &indices)?;
+    failures.set_phase(Phase::Pass2);
+
+    let failed = failures.failed_inputs();
+    let pass2_inputs: Vec<RuntimeEntityIdent> = selected
+        .as_ref()
+        .unwrap_or(&inputs)
+        .iter()
+        .filter(|i| !failed.contains(indices.relpath_for_tex_source(**i).unwrap()))
+        .copied()
+        .collect();
+
+    let mut p2r = pass2::Pass2Processor::new(merged_assets_id, indices)?;
     tex_pass::process_inputs(
-        &inputs,
+        &pass2_inputs,
         n_workers,
         &mut p2r,
-        &mut cache,
-        &mut indices,
+        cache,
+        indices,
+        &failures,
         bus.clone(),
     )
     .await?;
@@ -139,10 +210,12 @@ async fn primary_build_implementation<T: MessageBus + 'static>(
 
     maybe_modified_output_files.append(&mut p2r.into_potential_modified_outputs());
 
+    let complete = selected.is_none() && failures.is_empty();
     let (mut bus_tx, bus_rx) = new_sync_bus_channel();
+    let (mut indices, mut cache) = state.take().unwrap();
 
-    let handle = spawn_blocking(
-        move || -> Result<(Vec<RuntimeEntityIdent>, index::IndexCollection)> {
+    let handle = spawn_blocking(move || {
+        let result = (|| -> Result<Vec<RuntimeEntityIdent>> {
             // Generate the entrypoint file, and start generating the list of output
             // files that actually *were* modified. Unlike the TeX pass 2 and assets
             // steps, it's convenient for the entrypoint stage to figure out whether the
@@ -154,6 +227,57 @@ async fn primary_build_implementation<T: MessageBus + 'static>(
                 &mut bus_tx,
             )?;
 
+            // Same for the JSON export of the indices, the redirects of
+            // aliased entries, the tag listing pages, and the A–Z index pages.
+
+            modified_output_files.append(&mut index_export::maybe_export_indices_operation(
+                &mut cache,
+                &mut indices,
+                &mut bus_tx,
+            )?);
+
+            modified_output_files.append(&mut redirects::maybe_make_redirects_operation(
+                &mut cache,
+                &mut indices,
+                &mut bus_tx,
+            )?);
+
+            // If we're only building some of the inputs, only the pages of
+            // the tags and indices that they contribute to are updated.
+
+            let (only_tags, only_indices) = match &selected {
+                Some(selected) => {
+                    let ids: HashSet<InputId> = selected
+                        .iter()
+                        .filter_map(|i| match i {
+                            RuntimeEntityIdent::TexSourceFile(id) => Some(*id),
+                            _ => None,
+                        })
+                        .collect();
+
+                    (
+                        Some(indices.tags_of_inputs(&ids)),
+                        Some(indices.indices_defined_in_inputs(&ids)),
+                    )
+                }
+
+                None => (None, None),
+            };
+
+            modified_output_files.append(&mut tag_pages::maybe_make_tag_pages_operations(
+                &mut cache,
+                &mut indices,
+                only_tags.as_ref(),
+                &mut bus_tx,
+            )?);
+
+            modified_output_files.append(&mut index_pages::maybe_make_index_pages_operations(
+                &mut cache,
+                &mut indices,
+                only_indices.as_ref(),
+                &mut bus_tx,
+            )?);
+
             // Figure out which of the other outputs have been modified.
 
             for output in maybe_modified_output_files.drain(..) {
@@ -164,14 +288,31 @@ async fn primary_build_implementation<T: MessageBus + 'static>(
                 }
             }
 
-            Ok((modified_output_files, indices))
-        },
-    );
+            // Clean up the outputs that this build no longer creates, such as
+            // the pages of deleted or renamed entries. This can only be done
+            // when everything has been built successfully.
 
-    bus_rx.drain(bus.clone()).await;
-    let (modified_output_files, indices) = handle.await??;
+            if complete {
+                let stale = atry!(
+                    cache.remove_stale_outputs(&mut indices, &mut bus_tx);
+                    ["failed to remove stale build outputs"]
+                );
+
+                if !stale.is_empty() {
+                    tt_note!(bus_tx, "removed {} stale output files", stale.len());
+                }
+            }
+
+            Ok(modified_output_files)
+        })();
+
+        (indices, cache, result)
+    });
 
-    // TODO: rewrite the cache file state info!!!
+    bus_rx.drain(bus.clone()).await;
+    let (indices, cache, result) = handle.await?;
+    let (indices, _) = state.insert((indices, cache));
+    let modified_output_files = result?;
 
     // Translate the entity IDs into relative paths, if we care. That conversion
     // relies on the IndexCollection, which we're about to throw away, which is
@@ -191,14 +332,72 @@ async fn primary_build_implementation<T: MessageBus + 'static>(
 }
 
 /// The returned value is a list of the output files that were modified during
-/// the build. The paths are relative to the `build/` directory.
+/// the build. The paths are relative to the `build/` directory. If *explain*
+/// is true, the reasons why build operations need to be rerun are reported. If
+/// *only* is specified, only the inputs that it selects are fully built. If
+/// *keep_going* is true, the build carries on after inputs fail, building
+/// everything that doesn't depend on them, but an error is still returned at
+/// the end.
 pub async fn build_through_index<T: MessageBus + 'static>(
     n_workers: usize,
     collect_paths: bool,
+    explain: bool,
+    only: Option<inputs::InputFilter>,
+    keep_going: bool,
     mut bus: T,
 ) -> Result<Vec<String>> {
-    let result = primary_build_implementation(n_workers, collect_paths, bus.clone()).await;
+    let failures = FailureLog::new(keep_going);
+    let mut state = None;
+    let result = primary_build_implementation(
+        n_workers,
+        collect_paths,
+        explain,
+        only,
+        failures.clone(),
+        &mut state,
+        bus.clone(),
+    )
+    .await;
+
+    // Save what the cache has learned for the next build, even if this one
+    // failed, so that the files that were digested don't need to be reread.
+    // That includes the outputs replaced during this build, in case it didn't
+    // get as far as cleaning up stale outputs.
+
+    let saved = match state {
+        Some((indices, mut cache)) => {
+            let (mut bus_tx, bus_rx) = new_sync_bus_channel();
+
+            let handle = spawn_blocking(move || -> Result<()> {
+                cache.save_replaced_outputs(&mut bus_tx)?;
+
+                atry!(
+                    cache.save_file_digests(&indices);
+                    ["failed to save the build cache file digests"]
+                );
+
+                Ok(())
+            });
+
+            bus_rx.drain(bus.clone()).await;
+            handle.await?
+        }
+
+        None => Ok(()),
+    };
+
+    // Whether or not the build made it all the way through, summarize the
+    // inputs that failed, since the errors may be scattered all over the log.
+
+    failures.post_summary(&mut bus).await;
     let modified_files = result?;
+    saved?;
+
+    ensure!(
+        failures.is_empty(),
+        "{} build inputs failed",
+        failures.len()
+    );
 
     bus.post(Message::PhaseStarted("index-text".into())).await;
 
@@ -249,6 +448,20 @@ pub struct BuildArgs {
 
     #[arg(long)]
     no_dist: bool,
+
+    /// Explain why each build operation needs to be rerun.
+    #[arg(long)]
+    explain: bool,
+
+    /// Only build the inputs matching these paths or glob patterns, along with
+    /// the pages that they affect.
+    #[arg(long, num_args = 1.., value_name = "PATTERN")]
+    only: Vec<String>,
+
+    /// Keep going after inputs fail, building everything that doesn't depend
+    /// on them.
+    #[arg(long)]
+    keep_going: bool,
 }
 
 impl BuildArgs {
@@ -282,7 +495,21 @@ impl BuildArgs {
         };
 
         let t0 = Instant::now();
-        build_through_index(n_workers, false, bus.clone()).await?;
+        let only = if self.only.is_empty() {
+            None
+        } else {
+            Some(inputs::InputFilter::new(&self.only)?)
+        };
+
+        build_through_index(
+            n_workers,
+            false,
+            self.explain,
+            only,
+            self.keep_going,
+            bus.clone(),
+        )
+        .await?;
 
         if !self.no_dist {
             bus.post(Message::PhaseStarted("yarn-build".into())).await;
@@ -11,23 +11,28 @@
 use serde::{Deserialize, Serialize};
 use sha2::Digest;
 use std::{
-    collections::{BTreeMap, HashMap},
-    fs,
-    io::{self, ErrorKind},
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt, fs,
+    io::{self, ErrorKind, Write},
     path::{Path, PathBuf},
-    time::SystemTime,
+    sync::mpsc,
+    time::{Duration, SystemTime},
 };
 use tectonic_errors::{anyhow::Context, prelude::*};
-use tectonic_status_base::{tt_warning, StatusBackend};
+use tectonic_status_base::{tt_note, tt_warning, StatusBackend};
 use tempfile::NamedTempFile;
+use threadpool::ThreadPool;
+use walkdir::WalkDir;
 
 use crate::{
     config,
     index::IndexCollection,
+    metadata_snapshot,
     operation::{
         DigestComputer, DigestData, PersistEntity, PersistEntityIdent, RuntimeEntity,
         RuntimeEntityIdent,
     },
+    tex_pass,
 };
 
 /// Helper for caching file digests based on modification times.
@@ -38,13 +43,40 @@ use crate::{
 /// does, and we cache file digests based on their filesystem metadata,
 /// specifically modification times and sizes. If a file's mtime and size are
 /// what we have in our cache, we assume that its digest is as well.
+///
+/// That assumption fails if the file is modified again so soon after we
+/// digest it that its mtime doesn't change, given the finite resolution of
+/// filesystem timestamps. Like Git, we treat entries for files whose mtimes
+/// aren't comfortably older than the time when they were digested as "racy",
+/// and digest those files again when the entry is next freshened.
 #[derive(Clone, Copy, Debug, Deserialize, Serialize)]
 struct FileDigestEntry {
     digest: DigestData,
     mtime: SystemTime,
     size: u64,
+
+    /// When the file's metadata were read, just before it was digested.
+    checked: SystemTime,
 }
 
+/// The path of the file digest table, relative to the project root. The name
+/// includes the version of the format, since bincode data of different formats
+/// can't be reliably told apart.
+const FILE_DIGESTS_PATH: &str = "cache/file_digests_v2.dat";
+
+/// How much older than the time at which it was digested a file's mtime must
+/// be for the digest to be trusted. This allows for coarse filesystem
+/// timestamps, and for filesystem clocks that lag the system clock.
+const RACY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The path of the manifest of the outputs of the most recent build, relative
+/// to the project root.
+const OUTPUT_MANIFEST_PATH: &str = "cache/outputs.dat";
+
+/// How many times to try digesting a file that's being modified while we read
+/// it.
+const MAX_DIGEST_ATTEMPTS: usize = 3;
+
 /// Calculate the digest and size of a file, reading the whole thing.
 fn digest_of_file(p: impl AsRef<Path>) -> io::Result<(u64, DigestData)> {
     // We could get the file size from the filesystem metadata, but as long
@@ -58,28 +90,83 @@ fn digest_of_file(p: impl AsRef<Path>) -> io::Result<(u64, DigestData)> {
     Ok((size, digest))
 }
 
+/// Atomically replace a file with the bincode serialization of a value.
+fn write_bincode_atomically<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let dir = path.parent().unwrap();
+
+    atry!(
+        fs::create_dir_all(dir);
+        ["failed to create directory `{}`", dir.display()]
+    );
+
+    let mut f = atry!(
+        NamedTempFile::new_in(dir);
+        ["failed to create temporary file in `{}`", dir.display()]
+    );
+
+    let mut w = io::BufWriter::new(&mut f);
+
+    atry!(
+        bincode::serialize_into(&mut w, value);
+        ["failed to serialize bincode data into `{}`", path.display()]
+    );
+
+    atry!(
+        w.flush();
+        ["failed to write `{}`", path.display()]
+    );
+
+    drop(w);
+
+    atry!(
+        f.persist(path);
+        ["failed to persist temporary file to `{}`", path.display()]
+    );
+
+    Ok(())
+}
+
 impl FileDigestEntry {
     /// Create an entirely new file digest cache entry by reading the whole
     /// file.
+    ///
+    /// The file might be modified while we're reading it. We record the mtime
+    /// from *before* the read, so that such a modification will be noticed the
+    /// next time that the entry is freshened, and if the file is seen to change
+    /// during the read we try again a few times to get a consistent digest.
     fn create(p: impl AsRef<Path>) -> io::Result<FileDigestEntry> {
         let p = p.as_ref();
-        let md = fs::metadata(p)?;
-        let mtime = md.modified()?;
-        let (size, digest) = digest_of_file(p)?;
-
-        Ok(FileDigestEntry {
-            digest,
-            mtime,
-            size,
-        })
+        let mut attempts = 0;
+
+        loop {
+            let checked = SystemTime::now();
+            let md = fs::metadata(p)?;
+            let mtime = md.modified()?;
+            let (size, digest) = digest_of_file(p)?;
+
+            attempts += 1;
+            let md_after = fs::metadata(p)?;
+
+            if attempts == MAX_DIGEST_ATTEMPTS
+                || (md_after.modified()? == mtime && md_after.len() == size && md.len() == size)
+            {
+                return Ok(FileDigestEntry {
+                    digest,
+                    mtime,
+                    size: md.len(),
+                    checked,
+                });
+            }
+        }
     }
 
     /// Make sure that the information associated with this cache entry is
     /// fresh.
     ///
     /// If the mtime and size of the file at the specified path are the same as
-    /// what's been saved, assume that the file is unchanged and we don't need
-    /// to update the digest. Otherwise, recalculate the digest.
+    /// what's been saved, and the entry isn't racy, assume that the file is
+    /// unchanged and we don't need to update the digest. Otherwise, recalculate
+    /// the digest.
     ///
     /// We return a [`std::io::Result`] so that callers can easily test if the
     /// file in question did not exist.
@@ -89,15 +176,19 @@ impl FileDigestEntry {
         let md = fs::metadata(p)?;
         let mtime = md.modified()?;
 
-        if mtime != self.mtime || md.len() != self.size {
-            let (new_size, new_digest) = digest_of_file(p)?;
-            self.size = new_size;
-            self.digest = new_digest;
+        if mtime != self.mtime || md.len() != self.size || self.is_racy() {
+            *self = FileDigestEntry::create(p)?;
         }
 
         Ok(())
     }
 
+    /// Determine whether the file might have been modified after it was
+    /// digested without its mtime changing.
+    fn is_racy(&self) -> bool {
+        self.mtime + RACY_INTERVAL >= self.checked
+    }
+
     /// Create an entry for a while whose digest we're absolutely sure that we
     /// know; that is, a file that we've just created and closed.
     ///
@@ -113,6 +204,7 @@ impl FileDigestEntry {
         size: u64,
     ) -> Result<FileDigestEntry> {
         let p = p.as_ref();
+        let checked = SystemTime::now();
         let md = fs::metadata(p)?;
         let mtime = md.modified()?;
         let actual_size = md.len();
@@ -125,6 +217,7 @@ impl FileDigestEntry {
             digest,
             mtime,
             size,
+            checked,
         })
     }
 }
@@ -146,6 +239,28 @@ pub struct Cache {
     /// removed from this map and moved into the main map as they are
     /// referenced.
     loaded_file_digests: HashMap<RuntimeEntityIdent, FileDigestEntry>,
+
+    /// The pool of threads used to digest batches of files.
+    pool: ThreadPool,
+
+    /// The digest of the TeX bundle, computed on first use.
+    tex_bundle: Option<DigestData>,
+
+    /// The digest of the running executable, computed on first use.
+    program: Option<DigestData>,
+
+    /// Whether to explain why operations need to be rerun.
+    explain: bool,
+
+    /// The operations that have been probed during this session. In a
+    /// complete build, these are all of the operations that the build
+    /// performs.
+    probed_ops: HashSet<DigestData>,
+
+    /// Output files that were declared by previous runs of operations that
+    /// have been rerun during this session, as paths relative to the `build`
+    /// directory.
+    replaced_outputs: HashSet<String>,
 }
 
 impl Cache {
@@ -157,13 +272,7 @@ impl Cache {
     /// if anything funny happens, although the user can always blow away the
     /// entire cache instead.
     pub fn new(indices: &mut IndexCollection, status: &mut dyn StatusBackend) -> Result<Self> {
-        let root = config::get_root()?;
-
-        let mut cache = Cache {
-            root,
-            file_digests: HashMap::new(),
-            loaded_file_digests: HashMap::new(),
-        };
+        let mut cache = Cache::empty(config::get_root()?);
 
         // Now we can (try to) load up the cache of file digest info.
         //
@@ -171,27 +280,26 @@ impl Cache {
         // now I think it'll balance easy implementation and not being too
         // wasteful.
 
-        let persisted_files: Vec<(PersistEntityIdent, FileDigestEntry)> = {
-            let mut p_files = cache.root.clone();
-            p_files.push("cache");
-            p_files.push("file_digests.dat");
+        let p_files = cache.root.join(FILE_DIGESTS_PATH);
 
-            match fs::File::open(&p_files) {
-                Ok(f) => match bincode::deserialize_from(f) {
-                    Ok(pf) => pf,
+        let persisted_files: Vec<(PersistEntityIdent, FileDigestEntry)> = match fs::File::open(
+            &p_files,
+        ) {
+            Ok(f) => match bincode::deserialize_from(io::BufReader::new(f)) {
+                Ok(pf) => pf,
 
-                    Err(e) => {
-                        tt_warning!(status, "error deserializing file data in `{}`", p_files.display(); e.into());
-                        Vec::new()
-                    }
-                },
+                Err(e) => {
+                    tt_warning!(status, "error deserializing file data in `{}`", p_files.display(); e.into());
+                    Vec::new()
+                }
+            },
 
-                Err(ref e) if e.kind() == ErrorKind::NotFound => Vec::new(),
-                Err(e) => return Err(e).context(format!("failed to open `{}`", p_files.display())),
-            }
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e).context(format!("failed to open `{}`", p_files.display())),
         };
 
         for pf in persisted_files {
+            // Racy entries are kept, and dealt with when they're freshened.
             let pei = indices.runtime_ident(&pf.0);
             cache.loaded_file_digests.insert(pei, pf.1);
         }
@@ -201,6 +309,24 @@ impl Cache {
         Ok(cache)
     }
 
+    /// Create a cache manager rooted at the specified directory, without
+    /// loading any saved information.
+    fn empty(root: PathBuf) -> Self {
+        Cache {
+            root,
+            file_digests: HashMap::new(),
+            loaded_file_digests: HashMap::new(),
+            pool: threadpool::Builder::new()
+                .thread_name("digest".to_owned())
+                .build(),
+            tex_bundle: None,
+            program: None,
+            explain: false,
+            probed_ops: HashSet::new(),
+            replaced_outputs: HashSet::new(),
+        }
+    }
+
     /// Generate a path within the cache tree based on a digest, optionally
     /// creating its containing directory.
     ///
@@ -244,9 +370,6 @@ impl Cache {
         }
 
         // It's not in the active cache. Maybe it was on disk?
-        //
-        // We can just unwrap this result since right now the only idents that
-        // exist are ones associated with paths.
         let p = indices.path_for_runtime_ident(ident).unwrap();
 
         let fentry = match self.loaded_file_digests.remove(&ident) {
@@ -273,6 +396,79 @@ impl Cache {
         }))
     }
 
+    /// Make sure that we have up-to-date digests of a batch of entities,
+    /// reading the files that need it in parallel.
+    ///
+    /// This is purely an optimization: the results are stored in the same
+    /// tables consulted by [`Self::get_file_entity`], which will give the
+    /// same answers that it would have otherwise. Files that can't be probed
+    /// are left alone, so that the problem is reported when (and if) the
+    /// entity is actually requested.
+    fn freshen_entities(
+        &mut self,
+        idents: impl IntoIterator<Item = RuntimeEntityIdent>,
+        indices: &IndexCollection,
+    ) {
+        let (tx, rx) = mpsc::channel();
+        let mut queued = HashSet::new();
+
+        for ident in idents {
+            if !ident.is_file() || self.file_digests.contains_key(&ident) || !queued.insert(ident) {
+                continue;
+            }
+
+            let p = indices.path_for_runtime_ident(ident).unwrap();
+            let loaded = self.loaded_file_digests.remove(&ident);
+            let tx = tx.clone();
+
+            self.pool.execute(move || {
+                let fentry = match loaded {
+                    Some(mut fentry) => fentry.freshen(&p).map(|_| fentry),
+                    None => FileDigestEntry::create(&p),
+                };
+
+                // The receiver outlives all of the jobs.
+                tx.send((ident, loaded, fentry.ok())).unwrap();
+            });
+        }
+
+        drop(tx);
+
+        for (ident, loaded, fentry) in rx {
+            match fentry {
+                Some(fentry) => {
+                    self.file_digests.insert(ident, fentry);
+                }
+
+                None => {
+                    if let Some(loaded) = loaded {
+                        self.loaded_file_digests.insert(ident, loaded);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Prepare to check whether a batch of operations need to be rerun, by
+    /// making sure that we have up-to-date digests of all of their inputs.
+    ///
+    /// This reads the input files in parallel, which is much faster than
+    /// letting [`Self::operation_needs_rerun`] read them one at a time.
+    pub fn prefetch_operations(&mut self, opids: &[DigestData], indices: &mut IndexCollection) {
+        let mut idents = Vec::new();
+
+        for opid in opids {
+            // Problems will be reported when the operation is actually checked.
+            if let Ok(LoadedRecord::Saved(saved)) = self.read_operation(opid) {
+                for input in &saved.inputs {
+                    idents.push(indices.runtime_ident(&input.ident));
+                }
+            }
+        }
+
+        self.freshen_entities(idents, indices);
+    }
+
     /// Make a [`RuntimeEntity`] from a [`RuntimeEntityIdent`], ensuring that we
     /// have an up-to-date digest of its value.
     ///
@@ -285,9 +481,70 @@ impl Cache {
         ident: RuntimeEntityIdent,
         indices: &IndexCollection,
     ) -> Result<Option<RuntimeEntity>> {
-        // One day we may have different entity classes, but right now, we only
-        // have files.
-        self.get_file_entity(ident, indices)
+        if ident.is_file() {
+            return self.get_file_entity(ident, indices);
+        }
+
+        let mut dc = DigestComputer::default();
+
+        match ident {
+            RuntimeEntityIdent::EnvironmentVariable(_) => {
+                let name = indices.name_for_environment_variable(ident).unwrap();
+
+                // Distinguish unset variables from empty ones.
+                match std::env::var_os(name) {
+                    Some(v) => {
+                        dc.update([1]);
+                        dc.update(v.as_encoded_bytes());
+                    }
+                    None => dc.update([0]),
+                }
+            }
+
+            RuntimeEntityIdent::TexBundle => {
+                if self.tex_bundle.is_none() {
+                    self.tex_bundle = Some(atry!(
+                        tex_pass::tex_bundle_digest();
+                        ["failed to determine the identity of the TeX bundle"]
+                    ));
+                }
+
+                return Ok(Some(RuntimeEntity {
+                    ident,
+                    value_digest: self.tex_bundle.unwrap(),
+                }));
+            }
+
+            RuntimeEntityIdent::ProgramVersion => {
+                // The crate version is never bumped, so digest the executable
+                // itself: any rebuild with different logic changes it.
+                if self.program.is_none() {
+                    let exe = atry!(
+                        std::env::current_exe();
+                        ["failed to locate the running executable"]
+                    );
+
+                    let (_, digest) = atry!(
+                        digest_of_file(&exe);
+                        ["failed to digest the running executable `{}`", exe.display()]
+                    );
+
+                    self.program = Some(digest);
+                }
+
+                return Ok(Some(RuntimeEntity {
+                    ident,
+                    value_digest: self.program.unwrap(),
+                }));
+            }
+
+            _ => unreachable!(),
+        }
+
+        Ok(Some(RuntimeEntity {
+            ident,
+            value_digest: dc.finalize(),
+        }))
     }
 
     /// Make a [`RuntimeEntity`] from a [`RuntimeEntityIdent`], ensuring that we
@@ -330,10 +587,50 @@ impl Cache {
         })
     }
 
-    /// Returns true if the specified operation needs to be rerun.
+    /// Inform the cache about a file that has just been written outside of the
+    /// operation framework, and whose digest we therefore know.
     ///
-    /// The input cache data should have been set up information about the
-    /// expected inputs to the "runtime" form of the operation.
+    /// If the file had been probed previously, the cache would otherwise hold
+    /// on to its outdated digest.
+    pub fn update_known_file(
+        &mut self,
+        entity: &RuntimeEntity,
+        size: u64,
+        indices: &IndexCollection,
+    ) -> Result<()> {
+        let path = indices.path_for_runtime_ident(entity.ident)?;
+        let fentry = FileDigestEntry::create_for_known(path, entity.value_digest, size)?;
+        self.file_digests.insert(entity.ident, fentry);
+        Ok(())
+    }
+
+    /// Print explanations of why operations need to be rerun.
+    pub fn set_explain(&mut self, explain: bool) -> &mut Self {
+        self.explain = explain;
+        self
+    }
+
+    /// Save the table of file digests, so that future builds don't need to
+    /// reread every file.
+    ///
+    /// Only the entries of files that were referenced during this session are
+    /// saved, so that the table doesn't accumulate files that no longer exist.
+    /// The file is replaced atomically.
+    pub fn save_file_digests(&self, indices: &IndexCollection) -> Result<()> {
+        let path = self.root.join(FILE_DIGESTS_PATH);
+
+        let mut entries: Vec<(PersistEntityIdent, FileDigestEntry)> = self
+            .file_digests
+            .iter()
+            .map(|(rei, entry)| (indices.persist_ident(*rei), *entry))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        write_bincode_atomically(&path, &entries)
+    }
+
+    /// Determine whether the specified operation needs to be rerun, returning
+    /// the reason if so.
     ///
     /// The build can be skipped if implementation of the operation is unchanged
     /// (which will almost always be the case); the inputs to the operation are
@@ -343,100 +640,336 @@ impl Cache {
     /// and because that way we let the user manually edit outputs and rebuild
     /// if needed.
     ///
+    /// The *what* argument describes the operation. If explanations have been
+    /// activated with [`Self::set_explain`], it is used to report the reason
+    /// for the rerun to the status backend.
+    ///
     /// I/O errors relating to the cache data will be reported to the status
     /// backend but not exposed up the call chain, to try to keep things robust
     /// even if something funny happens in the cache.
     pub fn operation_needs_rerun(
         &mut self,
         opid: &DigestData,
+        what: &str,
         indices: &mut IndexCollection,
         status: &mut dyn StatusBackend,
-    ) -> Result<bool> {
-        // If the cache record for this operation doesn't exist, we must rerun
-        // the operation.
+    ) -> Result<Option<RerunReason>> {
+        self.probed_ops.insert(*opid);
+        let reason = self.rerun_reason(opid, indices, status)?;
 
-        let p_cache = self.cache_path(opid, "op", false).unwrap();
+        if self.explain {
+            if let Some(reason) = &reason {
+                tt_note!(status, "rerunning {}: {}", what, reason);
+            }
+        }
 
-        let mut f_cache = match fs::File::open(&p_cache) {
-            Ok(f) => f,
-            Err(e) => {
-                if e.kind() != ErrorKind::NotFound {
-                    tt_warning!(
-                        status,
-                        "error reading build cache file `{}`", p_cache.display();
-                        e.into()
-                    );
-                }
+        Ok(reason)
+    }
 
-                return Ok(true);
+    fn rerun_reason(
+        &mut self,
+        opid: &DigestData,
+        indices: &mut IndexCollection,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Option<RerunReason>> {
+        // If the cache record for this operation doesn't exist, or we can't
+        // understand it, we must rerun the operation.
+
+        let saved = match self.read_operation(opid) {
+            Ok(LoadedRecord::Saved(s)) => s,
+            Ok(LoadedRecord::Missing) => return Ok(Some(RerunReason::NoRecord)),
+            Ok(LoadedRecord::Outdated) => return Ok(Some(RerunReason::OutdatedRecord)),
+            Err(e) => {
+                let p_cache = self.cache_path(opid, "op", false).unwrap();
+                let message = e.to_string();
+                tt_warning!(status, "error reading build cache file `{}`", p_cache.display(); e);
+                return Ok(Some(RerunReason::BadRecord(message)));
             }
         };
 
-        // Gather saved inputs. These should be in sorted order so that we can
-        // regenerate the digest of the inputs in the same way that the sorted
-        // set produces.
+        // If any of the inputs have changed or disappeared, we must rerun the
+        // operation. Operations can have lots of inputs, so start by freshening
+        // them all in parallel.
 
-        let saved_inputs: Vec<PersistEntityIdent> = match bincode::deserialize_from(&mut f_cache) {
-            Ok(d) => d,
-            Err(e) => {
-                tt_warning!(status, "failed to deserialize bincode data from `{}`", p_cache.display(); e.into());
-                return Ok(true);
+        let reis: Vec<RuntimeEntityIdent> = saved
+            .inputs
+            .iter()
+            .map(|input| indices.runtime_ident(&input.ident))
+            .collect();
+
+        self.freshen_entities(reis.iter().copied(), indices);
+
+        for (input, rei) in saved.inputs.into_iter().zip(reis) {
+            match self.read_entity(rei, indices)? {
+                Some(e) if e.value_digest == input.value_digest => {}
+                Some(_) => return Ok(Some(RerunReason::ChangedInput(input.ident))),
+                None => return Ok(Some(RerunReason::MissingInput(input.ident))),
+            }
+        }
+
+        // If any of the outputs don't exist, we need to rerun.
+
+        for o in saved.outputs {
+            if !o.artifact_exists(self.root.clone())? {
+                return Ok(Some(RerunReason::MissingOutput(o)));
             }
+        }
+
+        // If we haven't spotted any problems, we don't need to rerun this step!
+
+        Ok(None)
+    }
+
+    /// Load the saved information about an operation, if it exists and is
+    /// in the current format.
+    ///
+    /// Unlike [`Self::operation_needs_rerun`], this doesn't check whether the
+    /// operation is up-to-date, so it doesn't need to probe any other files.
+    pub fn load_operation(&self, opid: &DigestData) -> Result<Option<SavedOperation>> {
+        match self.read_operation(opid)? {
+            LoadedRecord::Saved(s) => Ok(Some(s)),
+            _ => Ok(None),
+        }
+    }
+
+    fn read_operation(&self, opid: &DigestData) -> Result<LoadedRecord> {
+        let p_cache = self.cache_path(opid, "op", false).unwrap();
+
+        let mut f_cache = match fs::File::open(&p_cache) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(LoadedRecord::Missing),
+            Err(e) => return Err(e).context(format!("failed to open `{}`", p_cache.display())),
         };
 
-        let mut dc = DigestComputer::default();
+        // Records written before the format was versioned start with the
+        // length of the list of inputs, which will never match.
 
-        for pei in &saved_inputs {
-            let rei = indices.runtime_ident(pei);
+        let magic: u64 = atry!(
+            bincode::deserialize_from(&mut f_cache);
+            ["failed to deserialize bincode data from `{}`", p_cache.display()]
+        );
 
-            match self.read_entity(rei, indices)? {
-                Some(e) => {
-                    dc.update(e.value_digest);
-                }
+        if magic != OP_RECORD_MAGIC {
+            return Ok(LoadedRecord::Outdated);
+        }
 
-                None => {
-                    // The entity does not exist -- we need to rerun
-                    return Ok(true);
+        let inputs = atry!(
+            bincode::deserialize_from(&mut f_cache);
+            ["failed to deserialize bincode data from `{}`", p_cache.display()]
+        );
+
+        let outputs = atry!(
+            bincode::deserialize_from(&mut f_cache);
+            ["failed to deserialize bincode data from `{}`", p_cache.display()]
+        );
+
+        Ok(LoadedRecord::Saved(SavedOperation { inputs, outputs }))
+    }
+
+    /// Delete the files in the cache tree that aren't needed by any of the
+    /// specified operations, or just find them if *dry_run* is true.
+    ///
+    /// A file is needed if it's the saved record of one of the operations, or
+    /// one of their inputs or outputs, or one of a few special files that
+    /// live outside of the operation framework. The return value lists the
+    /// paths of the unneeded files, relative to the project root, and their
+    /// sizes.
+    pub fn collect_garbage(
+        &self,
+        live_ops: &HashSet<DigestData>,
+        dry_run: bool,
+    ) -> Result<Vec<(String, u64)>> {
+        let mut live_files: HashSet<String> = GC_KEEP_FILES.iter().map(|p| p.to_string()).collect();
+
+        for opid in live_ops {
+            if let Some(op) = self.load_operation(opid)? {
+                let inputs = op.inputs.into_iter().map(|e| e.ident);
+
+                for pei in inputs.chain(op.outputs) {
+                    if let PersistEntityIdent::OtherFile(relpath) = pei {
+                        live_files.insert(relpath);
+                    }
                 }
             }
         }
 
-        let actual_input_digest = dc.finalize();
+        let mut cache_dir = self.root.clone();
+        cache_dir.push("cache");
+        let mut garbage = Vec::new();
 
-        // If the inputs have changed, we must rerun the operation.
+        for entry in WalkDir::new(&cache_dir).contents_first(true) {
+            let entry = match entry {
+                Ok(e) => e,
+                Err(ref e) if e.io_error().map(|e| e.kind()) == Some(ErrorKind::NotFound) => {
+                    continue
+                }
+                Err(e) => {
+                    return Err(e).context(format!("failed to walk `{}`", cache_dir.display()))
+                }
+            };
 
-        let saved_input_digest = match bincode::deserialize_from(&mut f_cache) {
-            Ok(d) => d,
-            Err(e) => {
-                tt_warning!(status, "failed to deserialize bincode data from `{}` (1)", p_cache.display(); e.into());
-                return Ok(true);
+            let path = entry.path();
+
+            if entry.file_type().is_dir() {
+                // Clean up directories that we've emptied. This fails if the
+                // directory isn't empty, which is fine.
+                if !dry_run && path != cache_dir {
+                    let _ignored = fs::remove_dir(path);
+                }
+
+                continue;
             }
-        };
 
-        if actual_input_digest != saved_input_digest {
-            return Ok(true);
+            let relpath = match path.strip_prefix(&self.root).ok().and_then(|p| p.to_str()) {
+                Some(p) => p.replace(std::path::MAIN_SEPARATOR, "/"),
+                None => continue,
+            };
+
+            let is_live = match op_record_digest(&relpath) {
+                Some(opid) => live_ops.contains(&opid),
+                None => live_files.contains(&relpath),
+            };
+
+            if is_live {
+                continue;
+            }
+
+            let size = atry!(
+                entry.metadata();
+                ["failed to probe file `{}`", path.display()]
+            )
+            .len();
+
+            if !dry_run {
+                atry!(
+                    fs::remove_file(path);
+                    ["failed to delete file `{}`", path.display()]
+                );
+            }
+
+            garbage.push((relpath, size));
         }
 
-        // If we're still here, gather information about the outputs and check
-        // them. If any don't exist, we need to rerun.
+        Ok(garbage)
+    }
 
-        let saved_outputs: Vec<PersistEntityIdent> = match bincode::deserialize_from(&mut f_cache) {
-            Ok(d) => d,
-            Err(e) => {
-                tt_warning!(status, "failed to deserialize bincode data from `{}` (2)", p_cache.display(); e.into());
-                return Ok(true);
+    /// Read the manifest of the outputs of previous builds.
+    ///
+    /// Like the other cache files, a manifest that can't be deserialized is
+    /// reported as a warning and treated as empty.
+    fn read_output_manifest(&self, status: &mut dyn StatusBackend) -> Result<HashSet<String>> {
+        let manifest_path = self.root.join(OUTPUT_MANIFEST_PATH);
+
+        match fs::File::open(&manifest_path) {
+            Ok(f) => match bincode::deserialize_from(io::BufReader::new(f)) {
+                Ok(m) => Ok(m),
+
+                Err(e) => {
+                    tt_warning!(status, "error deserializing the output manifest `{}`", manifest_path.display(); e.into());
+                    Ok(HashSet::new())
+                }
+            },
+
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(HashSet::new()),
+            Err(e) => Err(e).context(format!("failed to open `{}`", manifest_path.display())),
+        }
+    }
+
+    /// Save a manifest of build outputs, replacing the existing one.
+    fn write_output_manifest(&self, outputs: HashSet<String>) -> Result<()> {
+        let mut outputs: Vec<String> = outputs.into_iter().collect();
+        outputs.sort();
+
+        atry!(
+            write_bincode_atomically(&self.root.join(OUTPUT_MANIFEST_PATH), &outputs);
+            ["failed to save the manifest of build outputs"]
+        );
+
+        Ok(())
+    }
+
+    /// Add the outputs replaced by operations rerun during this session to
+    /// the manifest of build outputs.
+    ///
+    /// This should be called at the end of every build. If the build didn't
+    /// get as far as [`Self::remove_stale_outputs`], this makes sure that
+    /// outputs that were renamed during it are still cleaned up by the next
+    /// complete build. Otherwise, there's nothing left to do.
+    pub fn save_replaced_outputs(&mut self, status: &mut dyn StatusBackend) -> Result<()> {
+        if self.replaced_outputs.is_empty() {
+            return Ok(());
+        }
+
+        let mut manifest = self.read_output_manifest(status)?;
+        manifest.extend(self.replaced_outputs.drain());
+        self.write_output_manifest(manifest)
+    }
+
+    /// Delete output files that were created by previous builds, but are no
+    /// longer created by any of the operations probed during this session.
+    ///
+    /// This should only be called at the end of a complete build. The outputs
+    /// of the build are saved in a manifest file, so that the outputs of
+    /// operations that are no longer performed at all, such as the pages of
+    /// deleted inputs, can be identified in the next build. Outputs are
+    /// deleted from both the `build` and `serve` directories. The return value
+    /// lists the paths of the deleted outputs, relative to those directories.
+    pub fn remove_stale_outputs(
+        &mut self,
+        indices: &mut IndexCollection,
+        status: &mut dyn StatusBackend,
+    ) -> Result<Vec<String>> {
+        let mut previous = self.read_output_manifest(status)?;
+        previous.extend(self.replaced_outputs.drain());
+
+        let mut current = HashSet::new();
+
+        for opid in &self.probed_ops {
+            if let Some(op) = self.load_operation(opid)? {
+                for output in op.outputs {
+                    if let PersistEntityIdent::OutputFile(relpath) = output {
+                        current.insert(relpath);
+                    }
+                }
             }
-        };
+        }
 
-        for o in &saved_outputs {
-            if !o.artifact_exists(self.root.clone())? {
-                return Ok(true);
+        let mut stale: Vec<String> = previous.difference(&current).cloned().collect();
+        stale.sort();
+
+        for relpath in &stale {
+            let rei = indices.make_output_file_ident(relpath);
+            self.file_digests.remove(&rei);
+            self.loaded_file_digests.remove(&rei);
+
+            for dir in ["build", "serve"] {
+                let base = self.root.join(dir);
+                let mut path = base.join(relpath);
+
+                match fs::remove_file(&path) {
+                    Ok(_) => {}
+                    Err(ref e) if e.kind() == ErrorKind::NotFound => continue,
+                    Err(e) => {
+                        return Err(e).context(format!("failed to delete `{}`", path.display()))
+                    }
+                }
+
+                // Clean up directories that we've emptied, such as those of
+                // renamed entries. This fails if a directory isn't empty, which
+                // is fine.
+
+                while path.pop() && path != base {
+                    if fs::remove_dir(&path).is_err() {
+                        break;
+                    }
+                }
             }
         }
 
-        // If we haven't spotted any problems, we don't need to rerun this step!
+        // Save the new manifest.
 
-        Ok(false)
+        self.write_output_manifest(current)?;
+        Ok(stale)
     }
 
     /// Mark an operation as complete and cache its information so that we can
@@ -453,12 +986,34 @@ impl Cache {
 
         let p_cache = self.cache_path(&data.ident, "op", true)?;
 
+        // If this operation has been run before, remember what its outputs
+        // were, in case they are no longer created.
+
+        if let Ok(Some(old)) = self.load_operation(&data.ident) {
+            for output in old.outputs {
+                if let PersistEntityIdent::OutputFile(relpath) = output {
+                    self.replaced_outputs.insert(relpath);
+                }
+            }
+        }
+
         let mut f_cache = atry!(
             NamedTempFile::new_in(&self.root);
             ["failed to create temporary file `{}`", self.root.display()]
         );
 
-        // Get digests for all of the inputs.
+        // Get digests for all of the inputs, including any that were only
+        // known by their paths.
+
+        for relpath in data.input_paths.drain(..) {
+            data.inputs
+                .push(RuntimeEntityIdent::new_other_file(relpath, indices));
+        }
+
+        // Every operation implicitly depends on the program that ran it, so
+        // that changes to our own logic invalidate the cache.
+
+        data.inputs.push(RuntimeEntityIdent::ProgramVersion);
 
         let mut input_set = SortedPersistEntitySet::default();
 
@@ -467,27 +1022,18 @@ impl Cache {
             input_set.insert_runtime(ent, indices);
         }
 
-        // Compute their total hash and save everything.
-
-        let mut input_idents: Vec<PersistEntityIdent> = Vec::with_capacity(data.inputs.len());
-        let mut dc = DigestComputer::default();
-
-        // BTreeMap doesn't have a stable drain API, so this is not as efficient as
-        // it could be.
-        for input in input_set.as_entities() {
-            input_idents.push(input.ident);
-            dc.update(input.value_digest);
-        }
+        // Save everything, including the digests of the individual inputs, so
+        // that we can tell which ones have changed.
 
         atry!(
-            bincode::serialize_into(&mut f_cache, &input_idents);
+            bincode::serialize_into(&mut f_cache, &OP_RECORD_MAGIC);
             ["failed to serialize bincode data into `{}`", p_cache.display()]
         );
 
-        let inputs_digest = dc.finalize();
+        let inputs: Vec<PersistEntity> = input_set.as_entities().collect();
 
         atry!(
-            bincode::serialize_into(&mut f_cache, &inputs_digest);
+            bincode::serialize_into(&mut f_cache, &inputs);
             ["failed to serialize bincode data into `{}`", p_cache.display()]
         );
 
@@ -529,10 +1075,104 @@ impl Cache {
     }
 }
 
+/// Files in the cache tree that are needed even though they aren't associated
+/// with any operation.
+const GC_KEEP_FILES: &[&str] = &[
+    FILE_DIGESTS_PATH,
+    OUTPUT_MANIFEST_PATH,
+    metadata_snapshot::SNAPSHOT_PATH,
+    metadata_snapshot::INDEX_SNAPSHOT_PATH,
+];
+
+/// If the path, relative to the project root, is that of the saved record of
+/// an operation, get the operation's identifier.
+///
+/// See [`Cache::cache_path`] for the layout.
+fn op_record_digest(relpath: &str) -> Option<DigestData> {
+    let rest = relpath.strip_prefix("cache/")?.strip_suffix(".op")?;
+    let (prefix, rest) = rest.split_once('/')?;
+
+    if prefix.len() != 2 {
+        return None;
+    }
+
+    let hex = format!("{prefix}{rest}");
+    let mut dd = DigestData::default();
+
+    if hex.len() != 2 * dd.len() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    for (i, byte) in dd.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+
+    Some(dd)
+}
+
+/// A value identifying the format of the saved records of operations. It
+/// should be changed whenever the format changes.
+const OP_RECORD_MAGIC: u64 = 0x7065_6469_616f_7002;
+
+/// The reason why an operation needs to be rerun.
+#[derive(Clone, Debug)]
+pub enum RerunReason {
+    /// There is no saved record of the operation.
+    NoRecord,
+
+    /// The saved record of the operation is in an old format.
+    OutdatedRecord,
+
+    /// The saved record of the operation couldn't be read. The value is the
+    /// error message.
+    BadRecord(String),
+
+    /// An input of the operation no longer exists.
+    MissingInput(PersistEntityIdent),
+
+    /// The value of an input of the operation has changed.
+    ChangedInput(PersistEntityIdent),
+
+    /// An output of the operation no longer exists.
+    MissingOutput(PersistEntityIdent),
+}
+
+impl fmt::Display for RerunReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RerunReason::NoRecord => write!(f, "it has never been run"),
+            RerunReason::OutdatedRecord => write!(f, "its cache record is in an old format"),
+            RerunReason::BadRecord(e) => write!(f, "its cache record couldn't be read: {}", e),
+            RerunReason::MissingInput(i) => write!(f, "input `{}` no longer exists", i),
+            RerunReason::ChangedInput(i) => write!(f, "input `{}` has changed", i),
+            RerunReason::MissingOutput(o) => write!(f, "output `{}` no longer exists", o),
+        }
+    }
+}
+
+/// The outcome of trying to read the saved record of an operation.
+enum LoadedRecord {
+    Missing,
+    Outdated,
+    Saved(SavedOperation),
+}
+
+/// The information saved about a completed operation.
+#[derive(Debug)]
+pub struct SavedOperation {
+    /// The inputs of the operation and the digests of their values, in
+    /// sorted order.
+    pub inputs: Vec<PersistEntity>,
+
+    /// The outputs of the operation.
+    pub outputs: Vec<PersistEntityIdent>,
+}
+
 #[derive(Debug)]
 pub struct OpCacheData {
     ident: DigestData,
     inputs: Vec<RuntimeEntityIdent>,
+    input_paths: Vec<String>,
     outputs: Vec<(RuntimeEntityIdent, Option<(DigestData, u64)>)>,
 }
 
@@ -542,6 +1182,7 @@ impl OpCacheData {
         OpCacheData {
             ident,
             inputs: Default::default(),
+            input_paths: Default::default(),
             outputs: Default::default(),
         }
     }
@@ -552,6 +1193,18 @@ impl OpCacheData {
         self
     }
 
+    /// Log an input to this operation that is a file not belonging to one of
+    /// the special categories, identified by its path relative to the project
+    /// root.
+    ///
+    /// This is useful in contexts where the [`IndexCollection`] is not
+    /// available, such as the TeX worker drivers. The path is converted into a
+    /// [`RuntimeEntityIdent`] when the operation is finalized.
+    pub fn add_other_file_input(&mut self, relpath: impl Into<String>) -> &mut Self {
+        self.input_paths.push(relpath.into());
+        self
+    }
+
     /// Register an output that is associated with this operation.
     ///
     /// Note that only the output identity is needed, not its full instance.
@@ -612,3 +1265,144 @@ impl SortedPersistEntitySet {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Overwrite a file and give it the specified mtime.
+    fn rewrite(p: &Path, content: &str, mtime: SystemTime) {
+        fs::write(p, content).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(p)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+    }
+
+    #[test]
+    fn digest_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let p = dir.path().join("a.txt");
+        let old = SystemTime::now() - Duration::from_secs(3600);
+        rewrite(&p, "hello", old);
+
+        let mut entry = FileDigestEntry::create(&p).unwrap();
+        assert_eq!(entry.size, 5);
+        assert_eq!(entry.mtime, old);
+        assert_eq!(entry.digest, digest_of_file(&p).unwrap().1);
+        assert!(!entry.is_racy());
+
+        // A trusted entry isn't redigested if the mtime and size match, but
+        // is if either changes.
+
+        rewrite(&p, "world", old);
+        entry.freshen(&p).unwrap();
+        assert_ne!(entry.digest, digest_of_file(&p).unwrap().1);
+
+        rewrite(&p, "worlds", old);
+        entry.freshen(&p).unwrap();
+        assert_eq!(entry.digest, digest_of_file(&p).unwrap().1);
+
+        // A file digested within the same timestamp tick as its last
+        // modification is always redigested.
+
+        let now = SystemTime::now();
+        rewrite(&p, "racy", now);
+        let mut entry = FileDigestEntry::create(&p).unwrap();
+        assert!(entry.is_racy());
+
+        rewrite(&p, "RACY", now);
+        entry.freshen(&p).unwrap();
+        assert_eq!(entry.digest, digest_of_file(&p).unwrap().1);
+
+        // Missing files are reported as such.
+
+        fs::remove_file(&p).unwrap();
+        let e = entry.freshen(&p).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NotFound);
+        let e = FileDigestEntry::create(&p).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NotFound);
+    }
+
+    /// The digest-relevant contents of a file digest table.
+    fn digests(
+        table: &HashMap<RuntimeEntityIdent, FileDigestEntry>,
+    ) -> BTreeMap<String, (DigestData, SystemTime, u64)> {
+        table
+            .iter()
+            .map(|(ident, e)| (format!("{ident:?}"), (e.digest, e.mtime, e.size)))
+            .collect()
+    }
+
+    #[test]
+    fn batch_freshening() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = SystemTime::now() - Duration::from_secs(3600);
+        let mut indices = IndexCollection::new().unwrap();
+
+        // Absolute paths replace the project root when the idents are
+        // resolved, so that we can use files in the temporary tree.
+        let mut idents = Vec::new();
+
+        for name in ["fresh.txt", "stale.txt", "new.txt", "missing.txt", "dir"] {
+            let p = dir.path().join(name);
+            idents.push(indices.make_other_file_ident(p.to_str().unwrap()));
+        }
+
+        let path = |i: usize| indices.path_for_runtime_ident(idents[i]).unwrap();
+
+        // Both caches start out with saved entries for the first, second, and
+        // fourth files. Then the second file changes, the third appears, and
+        // the fourth is deleted. The directory can't be read as a file.
+
+        rewrite(&path(0), "fresh", old);
+        rewrite(&path(1), "stale", old);
+        rewrite(&path(3), "missing", old);
+        fs::create_dir(path(4)).unwrap();
+
+        let mut loaded = HashMap::new();
+
+        for &i in &[0, 1, 3] {
+            loaded.insert(idents[i], FileDigestEntry::create(path(i)).unwrap());
+        }
+
+        rewrite(&path(1), "stale, but longer", old);
+        rewrite(&path(2), "new", old);
+        fs::remove_file(path(3)).unwrap();
+
+        let mut batch = Cache::empty(dir.path().to_owned());
+        batch.loaded_file_digests = loaded.clone();
+        let mut serial = Cache::empty(dir.path().to_owned());
+        serial.loaded_file_digests = loaded;
+
+        // Request each entity twice, so that the duplicate is handled too.
+        batch.freshen_entities(idents.iter().chain(&idents).copied(), &indices);
+
+        let mut batch_results = Vec::new();
+        let mut serial_results = Vec::new();
+
+        for &ident in &idents {
+            let b = batch.get_file_entity(ident, &indices);
+            batch_results.push(b.map(|r| r.map(|e| e.value_digest)).ok());
+            let s = serial.get_file_entity(ident, &indices);
+            serial_results.push(s.map(|r| r.map(|e| e.value_digest)).ok());
+        }
+
+        assert_eq!(batch_results, serial_results);
+        assert_eq!(batch_results[3], Some(None));
+        assert_eq!(batch_results[4], None);
+        assert_eq!(
+            batch_results[1],
+            Some(Some(digest_of_file(path(1)).unwrap().1))
+        );
+
+        assert_eq!(digests(&batch.file_digests), digests(&serial.file_digests));
+        assert_eq!(batch.file_digests.len(), 3);
+        assert_eq!(
+            digests(&batch.loaded_file_digests),
+            digests(&serial.loaded_file_digests)
+        );
+    }
+}
@@ -0,0 +1,312 @@
+// Copyright 2024 the Tectonic Project
+// Licensed under the MIT License
+
+//! The `cache` subcommand, for inspecting and maintaining the incremental build
+//! cache.
+
+use clap::{Args, Subcommand};
+use std::collections::HashSet;
+use tectonic_errors::prelude::*;
+use tectonic_status_base::{tt_note, StatusBackend};
+
+use crate::{
+    assets,
+    cache::Cache,
+    entrypoint_file, index, index_export, index_pages, inputs,
+    operation::{DigestData, PersistEntityIdent, RuntimeEntityIdent},
+    pass1, pass2, redirects, tag_pages,
+};
+
+/// Inspect and maintain the incremental build cache.
+#[derive(Args, Debug)]
+pub struct CacheArgs {
+    #[command(subcommand)]
+    action: CacheAction,
+}
+
+#[derive(Debug, Subcommand)]
+enum CacheAction {
+    /// Delete cached files that are no longer used by the build.
+    Gc(GcArgs),
+
+    /// Show the cached information about the operations processing an input.
+    Show(ShowArgs),
+}
+
+impl CacheArgs {
+    pub fn exec(self, status: &mut dyn StatusBackend) -> Result<()> {
+        match self.action {
+            CacheAction::Gc(a) => a.exec(status),
+            CacheAction::Show(a) => a.exec(status),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+struct GcArgs {
+    /// Report what would be deleted, without deleting anything.
+    #[arg(long)]
+    dry_run: bool,
+}
+
+impl GcArgs {
+    fn exec(self, status: &mut dyn StatusBackend) -> Result<()> {
+        let mut indices = index::IndexCollection::new()?;
+        atry!(
+            indices.load_user_indices();
+            ["failed to load user indices"]
+        );
+
+        let cache = atry!(
+            Cache::new(&mut indices, status);
+            ["error initializing build cache"]
+        );
+
+        let live_ops: HashSet<DigestData> = atry!(
+            live_operations(&cache, &mut indices);
+            ["failed to determine which build operations are in use"]
+        )
+        .into_iter()
+        .map(|op| op.opid)
+        .collect();
+
+        let garbage = atry!(
+            cache.collect_garbage(&live_ops, self.dry_run);
+            ["failed to clean up the build cache"]
+        );
+
+        let n_bytes: u64 = garbage.iter().map(|g| g.1).sum();
+
+        if self.dry_run {
+            for (relpath, _) in &garbage {
+                tt_note!(status, "would delete `{}`", relpath);
+            }
+
+            tt_note!(
+                status,
+                "would delete {} unused cache files, freeing {} bytes",
+                garbage.len(),
+                n_bytes
+            );
+        } else {
+            tt_note!(
+                status,
+                "deleted {} unused cache files, freeing {} bytes",
+                garbage.len(),
+                n_bytes
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Args, Debug)]
+struct ShowArgs {
+    /// The path of the TeX input, relative to the project root.
+    input: String,
+}
+
+impl ShowArgs {
+    fn exec(self, status: &mut dyn StatusBackend) -> Result<()> {
+        let mut indices = index::IndexCollection::new()?;
+        atry!(
+            indices.load_user_indices();
+            ["failed to load user indices"]
+        );
+
+        let mut cache = atry!(
+            Cache::new(&mut indices, status);
+            ["error initializing build cache"]
+        );
+
+        let relpath = self.input.trim_start_matches("./");
+        let input = RuntimeEntityIdent::new_tex_source(relpath, &mut indices);
+        let input_path = indices.path_for_runtime_ident(input)?;
+
+        ensure!(
+            input_path.is_file(),
+            "no such input file `{}`",
+            input_path.display()
+        );
+
+        let ops = [
+            ("TeX pass 1", pass1::operation_id(input, &indices)),
+            ("TeX pass 2", pass2::operation_id(input, &indices)),
+        ];
+
+        for (i, (desc, opid)) in ops.iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+
+            println!("{} of `{}` (operation {:x}):", desc, relpath, opid);
+
+            let what = format!("{} of `{}`", desc, relpath);
+            let reason = atry!(
+                cache.operation_needs_rerun(opid, &what, &mut indices, status);
+                ["failed to probe cache for operation {:x}", opid]
+            );
+
+            match reason {
+                Some(r) => println!("  status: needs rerun because {}", r),
+                None => println!("  status: up to date"),
+            }
+
+            let saved = match cache.load_operation(opid)? {
+                Some(s) => s,
+                None => continue,
+            };
+
+            println!("  inputs:");
+
+            for input in &saved.inputs {
+                println!("    {} {:x}", input.ident, input.value_digest);
+            }
+
+            println!("  outputs:");
+
+            for output in &saved.outputs {
+                println!("    {}", output);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An operation that the build would perform given the current inputs.
+#[derive(Debug)]
+pub struct LiveOperation {
+    /// The kind of the operation, such as `pass1`.
+    pub kind: &'static str,
+
+    /// A description of what the operation acts on, if it is one of many
+    /// operations of its kind.
+    pub subject: Option<String>,
+
+    /// The identifier of the operation.
+    pub opid: DigestData,
+}
+
+impl LiveOperation {
+    fn new(kind: &'static str, subject: Option<String>, opid: DigestData) -> Self {
+        LiveOperation {
+            kind,
+            subject,
+            opid,
+        }
+    }
+}
+
+/// Compute the identifiers of all of the operations that the build would
+/// perform given the current inputs, in the order that the build performs
+/// them.
+///
+/// Nothing is run, but the saved record of the cross-indexing operation is
+/// consulted to learn which tags are in use.
+pub fn live_operations(
+    cache: &Cache,
+    indices: &mut index::IndexCollection,
+) -> Result<Vec<LiveOperation>> {
+    // The build orders the intermediate files of the first pass by the IDs of
+    // their inputs, which we must reproduce to get the same operation IDs.
+
+    let mut inputs = inputs::collect_inputs(indices)?;
+    inputs.sort();
+
+    let mut live = Vec::new();
+    let mut pass2_ops = Vec::with_capacity(inputs.len());
+    let mut asset_ids = Vec::with_capacity(inputs.len());
+    let mut metadata_ids = Vec::with_capacity(inputs.len());
+
+    for input in inputs {
+        let relpath = indices.relpath_for_tex_source(input).map(|p| p.to_owned());
+        live.push(LiveOperation::new(
+            "pass1",
+            relpath.clone(),
+            pass1::operation_id(input, indices),
+        ));
+        pass2_ops.push(LiveOperation::new(
+            "pass2",
+            relpath,
+            pass2::operation_id(input, indices),
+        ));
+
+        let (assets_id, metadata_id) = pass1::intermediate_idents(input, indices);
+        asset_ids.push(assets_id);
+        metadata_ids.push(metadata_id);
+    }
+
+    let cross_index_opid = index::cross_index_operation_id(&metadata_ids, indices);
+    live.push(LiveOperation::new("cross_index", None, cross_index_opid));
+
+    live.push(LiveOperation::new(
+        "merge_assets",
+        None,
+        assets::merge_operation_id(&asset_ids, indices),
+    ));
+    let merged_assets_id = assets::merged_assets_ident(indices);
+    live.push(LiveOperation::new(
+        "emit_assets",
+        None,
+        assets::emit_operation_id(merged_assets_id, indices),
+    ));
+
+    live.append(&mut pass2_ops);
+
+    live.push(LiveOperation::new(
+        "entrypoint",
+        None,
+        entrypoint_file::operation_id(indices),
+    ));
+    live.push(LiveOperation::new(
+        "index_export",
+        None,
+        index_export::operation_id(indices),
+    ));
+    live.push(LiveOperation::new(
+        "redirects",
+        None,
+        redirects::operation_id(indices),
+    ));
+
+    // The tags are only known once the indices have been constructed, but the
+    // cross-indexing operation records the per-tag files that it writes.
+
+    live.push(LiveOperation::new(
+        "tag_index",
+        None,
+        tag_pages::index_operation_id(indices),
+    ));
+
+    if let Some(op) = cache.load_operation(&cross_index_opid)? {
+        for output in &op.outputs {
+            if let PersistEntityIdent::OtherFile(relpath) = output {
+                if let Some(tag) = relpath
+                    .strip_prefix("cache/tags/")
+                    .and_then(|t| t.strip_suffix(".csv"))
+                {
+                    live.push(LiveOperation::new(
+                        "tag_page",
+                        Some(tag.to_owned()),
+                        tag_pages::page_operation_id(tag, indices),
+                    ));
+                }
+            }
+        }
+    }
+
+    let listings: Vec<(String, String)> = indices
+        .listings()
+        .into_iter()
+        .map(|(name, title)| (name.to_owned(), title.to_owned()))
+        .collect();
+
+    for (name, title) in listings {
+        let opid = index_pages::operation_id(&name, &title, indices);
+        live.push(LiveOperation::new("index_page", Some(name), opid));
+    }
+
+    Ok(live)
+}
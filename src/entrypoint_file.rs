@@ -12,9 +12,20 @@ use tectonic_status_base::StatusBackend;
 use crate::{
     cache::{Cache, OpCacheData},
     index::IndexCollection,
-    operation::{DigestComputer, OpOutputStream, RuntimeEntityIdent},
+    operation::{DigestComputer, DigestData, OpOutputStream, RuntimeEntityIdent},
 };
 
+/// Compute the identifier of the entrypoint creation operation.
+pub fn operation_id(indices: &mut IndexCollection) -> DigestData {
+    let mut dc = DigestComputer::default();
+    dc.update("make_entrypoint_v3");
+
+    let input = RuntimeEntityIdent::new_other_file("cache/idx/outputs.csv", indices);
+    input.update_digest(&mut dc, indices);
+
+    dc.finalize()
+}
+
 /// Potentially emit the "entrypoint" files used to drive Parcel.js.
 ///
 /// The return value is a list of identifiers of any entrypoints that were
@@ -29,20 +40,15 @@ pub fn maybe_make_entrypoint_operation(
     // Set up the information about the operation. By construction, the
     // "outputs" index CSV file contains exactly what we need.
 
-    let mut dc = DigestComputer::default();
-    dc.update("make_entrypoint_v2");
-
     let input = RuntimeEntityIdent::new_other_file("cache/idx/outputs.csv", indices);
-    input.update_digest(&mut dc, indices);
-
-    let opid = dc.finalize();
+    let opid = operation_id(indices);
 
-    let needs_rerun = atry!(
-        cache.operation_needs_rerun(&opid, indices, status);
+    let rerun_reason = atry!(
+        cache.operation_needs_rerun(&opid, "the entrypoint creation", indices, status);
         ["failed to probe cache for entrypoint creation operation"]
     );
 
-    if !needs_rerun {
+    if rerun_reason.is_none() {
         return Ok(modified);
     }
 
@@ -117,7 +123,8 @@ pub fn maybe_make_entrypoint_operation(
     // JSON file because Parcel gives the JSON a magic hashed URL that we need
     // to propagate into the `web/` code. We have to give the file an extension
     // that isn't `.json` because otherwise Parcel will try to be smart and
-    // inline the JSON data, breaking the scheme.
+    // inline the JSON data, breaking the scheme. The same goes for the
+    // "indicesUrl", which gives the URL of the JSON export of the indices.
 
     let output = RuntimeEntityIdent::new_other_file("build/entrypoint.ts", indices);
     let orig_digest = cache.unconditional_entity(output, indices)?.value_digest;
@@ -131,6 +138,7 @@ pub fn maybe_make_entrypoint_operation(
             output_stream,
             r#"import {{ buildSpecificSettings }} from "../web/base.js";
 buildSpecificSettings.indexUrl = require("url:./search_index.json.data");
+buildSpecificSettings.indicesUrl = require("url:./indices.json.data");
 import {{ mountIt }} from "../web/index.js";
 mountIt(document);"#
         );
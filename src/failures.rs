@@ -0,0 +1,285 @@
+// Copyright 2024 the Tectonic Project
+// Licensed under the MIT License
+
+//! Tracking the inputs that fail during a build.
+//!
+//! Errors stream by as the build progresses, which makes it tedious to work
+//! out what's broken in a big build. So, we keep a log of the inputs that
+//! fail, and the phase in which each one failed, and summarize them at the
+//! end of the build. The first error message reported for each input is
+//! captured by wrapping the build's message bus with a [`RecordingBus`].
+//!
+//! In "keep going" mode, inputs that fail are dropped from the rest of the
+//! build, rather than stopping it at the end of the current phase.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use crate::messages::{AlertMessage, Message, MessageBus};
+
+/// A phase of the build in which individual inputs can fail.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Phase {
+    Pass1,
+    Indexing,
+    Pass2,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Phase::Pass1 => write!(f, "pass 1"),
+            Phase::Indexing => write!(f, "indexing"),
+            Phase::Pass2 => write!(f, "pass 2"),
+        }
+    }
+}
+
+/// A shared log of the inputs that have failed during a build.
+#[derive(Clone, Debug)]
+pub struct FailureLog {
+    keep_going: bool,
+    inner: Arc<Mutex<FailureLogInner>>,
+}
+
+#[derive(Debug)]
+struct FailureLogInner {
+    phase: Phase,
+
+    /// The failed inputs, in order of failure.
+    failures: Vec<(Phase, String)>,
+
+    /// The first error message reported for each input.
+    first_errors: HashMap<String, String>,
+}
+
+impl FailureLog {
+    /// Create a new log. If *keep_going* is true, the build should carry on
+    /// with the inputs that haven't failed.
+    pub fn new(keep_going: bool) -> Self {
+        FailureLog {
+            keep_going,
+            inner: Arc::new(Mutex::new(FailureLogInner {
+                phase: Phase::Pass1,
+                failures: Vec::new(),
+                first_errors: HashMap::new(),
+            })),
+        }
+    }
+
+    pub fn keep_going(&self) -> bool {
+        self.keep_going
+    }
+
+    /// Set the phase to be associated with subsequent failures.
+    pub fn set_phase(&self, phase: Phase) {
+        self.inner.lock().unwrap().phase = phase;
+    }
+
+    /// Record that an input, identified by its path relative to the project
+    /// root, has failed in the current phase.
+    pub fn fail(&self, input: impl Into<String>) {
+        let mut inner = self.inner.lock().unwrap();
+        let input = input.into();
+
+        if !inner.failures.iter().any(|(_, i)| *i == input) {
+            let phase = inner.phase;
+            inner.failures.push((phase, input));
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.lock().unwrap().failures.is_empty()
+    }
+
+    /// Get the number of inputs that have failed.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().failures.len()
+    }
+
+    /// Get the paths of the inputs that have failed.
+    pub fn failed_inputs(&self) -> HashSet<String> {
+        self.inner
+            .lock()
+            .unwrap()
+            .failures
+            .iter()
+            .map(|(_, i)| i.clone())
+            .collect()
+    }
+
+    /// Record an error message, if it's the first one for its file.
+    fn note_error(&self, alert: &AlertMessage) {
+        let file = match &alert.file {
+            Some(f) => f,
+            None => return,
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+
+        if !inner.first_errors.contains_key(file) {
+            let message = match alert.line {
+                Some(l) => format!("line {}: {}", l, alert.message),
+                None => alert.message.clone(),
+            };
+
+            inner.first_errors.insert(file.clone(), message);
+        }
+    }
+
+    /// Post a summary of the failed inputs, grouped by phase, if there are
+    /// any.
+    pub async fn post_summary<B: MessageBus>(&self, bus: &mut B) {
+        let mut groups: Vec<(Phase, Vec<String>)> = Vec::new();
+
+        {
+            let inner = self.inner.lock().unwrap();
+            let mut failures = inner.failures.clone();
+            failures.sort();
+
+            for (phase, input) in failures {
+                let message = inner
+                    .first_errors
+                    .get(&input)
+                    .map(|m| m.as_str())
+                    .unwrap_or("(no error message was reported)");
+                let line = format!("{}: {}", input, message);
+
+                match groups.last_mut() {
+                    Some((p, lines)) if *p == phase => lines.push(line),
+                    _ => groups.push((phase, vec![line])),
+                }
+            }
+        }
+
+        for (phase, lines) in groups {
+            bus.post(Message::Error(AlertMessage {
+                file: None,
+                line: None,
+                message: format!("inputs that failed in {} ({}):", phase, lines.len()),
+                context: lines,
+            }))
+            .await;
+        }
+    }
+}
+
+/// A message bus that records the first error reported for each file in a
+/// [`FailureLog`], and passes all messages on to another bus.
+#[derive(Clone)]
+pub struct RecordingBus<B> {
+    inner: B,
+    log: FailureLog,
+}
+
+impl<B: MessageBus> RecordingBus<B> {
+    pub fn new(inner: B, log: FailureLog) -> Self {
+        RecordingBus { inner, log }
+    }
+}
+
+impl<B: MessageBus> MessageBus for RecordingBus<B> {
+    async fn post(&mut self, msg: Message) {
+        if let Message::Error(alert) = &msg {
+            self.log.note_error(alert);
+        }
+
+        self.inner.post(msg).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bus that keeps the errors posted to it.
+    #[derive(Clone, Default)]
+    struct CollectingBus {
+        errors: Arc<Mutex<Vec<AlertMessage>>>,
+    }
+
+    impl MessageBus for CollectingBus {
+        async fn post(&mut self, msg: Message) {
+            if let Message::Error(alert) = msg {
+                self.errors.lock().unwrap().push(alert);
+            }
+        }
+    }
+
+    fn error(file: Option<&str>, line: Option<u32>, message: &str) -> Message {
+        Message::Error(AlertMessage {
+            file: file.map(|f| f.to_owned()),
+            line,
+            message: message.to_owned(),
+            context: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn dedup_and_phases() {
+        let log = FailureLog::new(true);
+        assert!(log.is_empty());
+
+        log.fail("txt/a.tex");
+        log.set_phase(Phase::Indexing);
+        log.fail("txt/a.tex");
+        log.fail("txt/b.tex");
+
+        assert_eq!(log.len(), 2);
+
+        let inner = log.inner.lock().unwrap();
+        assert_eq!(
+            inner.failures,
+            vec![
+                (Phase::Pass1, "txt/a.tex".to_owned()),
+                (Phase::Indexing, "txt/b.tex".to_owned()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn first_errors_and_summary() {
+        let log = FailureLog::new(false);
+        let collector = CollectingBus::default();
+        let mut bus = RecordingBus::new(collector.clone(), log.clone());
+
+        bus.post(error(Some("txt/b.tex"), Some(3), "first")).await;
+        bus.post(error(Some("txt/b.tex"), None, "second")).await;
+        bus.post(error(None, None, "unattributed")).await;
+
+        log.set_phase(Phase::Pass2);
+        log.fail("txt/c.tex");
+        log.set_phase(Phase::Pass1);
+        log.fail("txt/b.tex");
+        log.fail("txt/a.tex");
+
+        collector.errors.lock().unwrap().clear();
+        log.post_summary(&mut bus).await;
+
+        let errors = collector.errors.lock().unwrap();
+        let summary: Vec<_> = errors
+            .iter()
+            .map(|a| (a.message.as_str(), a.context.clone()))
+            .collect();
+
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "inputs that failed in pass 1 (2):",
+                    vec![
+                        "txt/a.tex: (no error message was reported)".to_owned(),
+                        "txt/b.tex: line 3: first".to_owned(),
+                    ]
+                ),
+                (
+                    "inputs that failed in pass 2 (1):",
+                    vec!["txt/c.tex: (no error message was reported)".to_owned()]
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright 2024 the Tectonic Project
+// Licensed under the MIT License
+
+//! The `graph` subcommand, exporting the build graph.
+//!
+//! The graph is reconstructed from the saved records of the operations that
+//! the build would perform given the current inputs, so it reflects the state
+//! of things as of the most recent build. Operations that haven't been run yet
+//! are included, but without any edges. Only file entities are shown: the
+//! non-file inputs shared by many operations, such as the TeX bundle, would
+//! just clutter things up.
+
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+use tectonic_errors::prelude::*;
+use tectonic_status_base::{tt_note, StatusBackend};
+
+use crate::{
+    cache::Cache,
+    cache_cli::{live_operations, LiveOperation},
+    index,
+    operation::PersistEntityIdent,
+};
+
+/// Export the build graph, connecting the inputs and outputs of the build
+/// operations.
+#[derive(Args, Debug)]
+pub struct GraphArgs {
+    /// The format of the graph.
+    #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+    format: GraphFormat,
+
+    /// The file to write the graph to, rather than standard output.
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum GraphFormat {
+    /// The Graphviz DOT language.
+    Dot,
+
+    /// A JSON object with lists of nodes and edges.
+    Json,
+}
+
+impl GraphArgs {
+    pub fn exec(self, status: &mut dyn StatusBackend) -> Result<()> {
+        let mut indices = index::IndexCollection::new()?;
+        atry!(
+            indices.load_user_indices();
+            ["failed to load user indices"]
+        );
+
+        let cache = atry!(
+            Cache::new(&mut indices, status);
+            ["error initializing build cache"]
+        );
+
+        let live_ops = atry!(
+            live_operations(&cache, &mut indices);
+            ["failed to determine which build operations are in use"]
+        );
+
+        let graph = atry!(
+            Graph::from_operations(&live_ops, &cache);
+            ["failed to load the build graph"]
+        );
+
+        if graph.n_unrecorded > 0 {
+            tt_note!(
+                status,
+                "{} operations have not been run yet, so their inputs and outputs are unknown",
+                graph.n_unrecorded
+            );
+        }
+
+        let text = match self.format {
+            GraphFormat::Dot => graph.to_dot()?,
+            GraphFormat::Json => atry!(
+                serde_json::to_string_pretty(&graph);
+                ["failed to serialize the build graph"]
+            ),
+        };
+
+        match self.output {
+            Some(p) => {
+                atry!(
+                    fs::write(&p, text + "\n");
+                    ["failed to write `{}`", p.display()]
+                );
+            }
+
+            None => {
+                let mut stdout = io::stdout().lock();
+                atry!(
+                    writeln!(stdout, "{}", text);
+                    ["failed to write the graph to standard output"]
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Graph {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+
+    #[serde(skip)]
+    n_unrecorded: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct Node {
+    id: String,
+    kind: &'static str,
+    label: String,
+
+    /// For operations, whether the cache has a record of them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recorded: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+struct Edge {
+    from: String,
+    to: String,
+}
+
+impl Graph {
+    fn from_operations(ops: &[LiveOperation], cache: &Cache) -> Result<Self> {
+        let mut graph = Graph {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            n_unrecorded: 0,
+        };
+
+        let mut file_ids: HashMap<PersistEntityIdent, String> = HashMap::new();
+
+        for op in ops {
+            let op_id = format!("op:{:x}", op.opid);
+            let saved = cache.load_operation(&op.opid)?;
+
+            if saved.is_none() {
+                graph.n_unrecorded += 1;
+            }
+
+            graph.nodes.push(Node {
+                id: op_id.clone(),
+                kind: op.kind,
+                label: match &op.subject {
+                    Some(s) => format!("{} {}", op.kind, s),
+                    None => op.kind.to_owned(),
+                },
+                recorded: Some(saved.is_some()),
+            });
+
+            let saved = match saved {
+                Some(s) => s,
+                None => continue,
+            };
+
+            for input in saved.inputs {
+                if let Some(id) = graph.file_node(input.ident, &mut file_ids) {
+                    graph.edges.push(Edge {
+                        from: id,
+                        to: op_id.clone(),
+                    });
+                }
+            }
+
+            for output in saved.outputs {
+                if let Some(id) = graph.file_node(output, &mut file_ids) {
+                    graph.edges.push(Edge {
+                        from: op_id.clone(),
+                        to: id,
+                    });
+                }
+            }
+        }
+
+        Ok(graph)
+    }
+
+    /// Get the node ID of a file entity, creating its node if needed. Returns
+    /// None if the entity isn't a file.
+    fn file_node(
+        &mut self,
+        ident: PersistEntityIdent,
+        file_ids: &mut HashMap<PersistEntityIdent, String>,
+    ) -> Option<String> {
+        if let Some(id) = file_ids.get(&ident) {
+            return Some(id.clone());
+        }
+
+        // The kind is part of the ID, since files of different kinds can have
+        // the same path: a TeX source that's `\input` by another one is also
+        // a dependency of the latter as an "other" file.
+        let (kind, prefix) = match ident {
+            PersistEntityIdent::TexSourceFile(_) => ("tex_source_file", "tex"),
+            PersistEntityIdent::OtherFile(_) => ("other_file", "other"),
+            PersistEntityIdent::OutputFile(_) => ("output_file", "output"),
+            _ => return None,
+        };
+
+        let label = ident.to_string();
+        let id = format!("{}:{}", prefix, label);
+
+        self.nodes.push(Node {
+            id: id.clone(),
+            kind,
+            label,
+            recorded: None,
+        });
+
+        file_ids.insert(ident, id.clone());
+        Some(id)
+    }
+
+    fn to_dot(&self) -> Result<String> {
+        let mut dot = String::from("digraph build {\n  rankdir=LR;\n");
+
+        for node in &self.nodes {
+            let attrs = match (node.kind, node.recorded) {
+                ("tex_source_file", _) => "shape=note, style=filled, fillcolor=lightblue",
+                ("other_file", _) => "shape=note",
+                ("output_file", _) => "shape=note, style=filled, fillcolor=palegreen",
+                (_, Some(false)) => "shape=box, style=dashed",
+                _ => "shape=box, style=rounded",
+            };
+
+            writeln!(
+                dot,
+                "  {} [label={}, {}];",
+                dot_quote(&node.id),
+                dot_quote(&node.label),
+                attrs
+            )?;
+        }
+
+        for edge in &self.edges {
+            writeln!(
+                dot,
+                "  {} -> {};",
+                dot_quote(&edge.from),
+                dot_quote(&edge.to)
+            )?;
+        }
+
+        dot.push('}');
+        Ok(dot)
+    }
+}
+
+/// Quote a string for use as a DOT identifier.
+fn dot_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_node_ids() {
+        let mut graph = Graph {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            n_unrecorded: 0,
+        };
+        let mut file_ids = HashMap::new();
+
+        let tex = PersistEntityIdent::TexSourceFile("txt/x.tex".to_owned());
+        let other = PersistEntityIdent::OtherFile("txt/x.tex".to_owned());
+        let output = PersistEntityIdent::OutputFile("x.html".to_owned());
+
+        let tex_id = graph.file_node(tex.clone(), &mut file_ids).unwrap();
+        let other_id = graph.file_node(other, &mut file_ids).unwrap();
+        let output_id = graph.file_node(output, &mut file_ids).unwrap();
+
+        assert_eq!(tex_id, "tex:txt/x.tex");
+        assert_eq!(other_id, "other:txt/x.tex");
+        assert_eq!(output_id, "output:build/x.html");
+        assert_eq!(graph.file_node(tex, &mut file_ids).unwrap(), tex_id);
+        assert_eq!(graph.nodes.len(), 3);
+        assert!(graph
+            .file_node(PersistEntityIdent::TexBundle, &mut file_ids)
+            .is_none());
+    }
+}
@@ -0,0 +1,283 @@
+// Copyright 2024 the Tectonic Project
+// Licensed under the MIT License
+
+//! Helpers for HTML files that we generate directly, rather than through TeX.
+
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::Path,
+};
+use tectonic_errors::prelude::*;
+use tectonic_status_base::StatusBackend;
+
+use crate::{
+    cache::{Cache, OpCacheData},
+    index::IndexCollection,
+    operation::{DigestData, OpOutputStream, RuntimeEntityIdent},
+};
+
+/// Escape text for inclusion in HTML content or a double-quoted attribute
+/// value.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// The values of the variables of the main HTML template for a page that
+/// isn't generated by TeX.
+pub struct TemplateVars<'a> {
+    /// The name of the book that the page belongs to, as plain text.
+    pub book_name: &'a str,
+
+    /// The page title, as plain text.
+    pub title: &'a str,
+
+    /// The relative path from the page to the top of the output tree.
+    pub rel_top: &'a str,
+
+    /// The page content, as HTML.
+    pub content: &'a str,
+}
+
+/// Fill in the main HTML template, `cls/template.html`, for a page that isn't
+/// generated by TeX.
+///
+/// This only supports the handful of variables used by the template. Other
+/// `{{ ... }}` placeholders are left alone. The template is scanned in a single
+/// pass, so placeholders appearing in the values aren't substituted.
+pub fn render_template(template: &str, vars: &TemplateVars) -> String {
+    let mut rendered = String::with_capacity(template.len() + vars.content.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(n) => start + n + 2,
+            None => break,
+        };
+
+        rendered.push_str(&rest[..start]);
+
+        match rest[start + 2..end - 2].trim() {
+            "pediaTitle" => rendered.push_str(&escape(vars.title)),
+            "pediaBookName" => rendered.push_str(&escape(vars.book_name)),
+            "tduxRelTop" => rendered.push_str(vars.rel_top),
+            "tduxContent | safe" => rendered.push_str(vars.content),
+            _ => rendered.push_str(&rest[start..end]),
+        }
+
+        rest = &rest[end..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Extract the name of the reference book from the text of
+/// `cls/pedia/booknames.tex`, which the TeX inputs use to label the entry
+/// pages.
+fn reference_book_name(text: &str) -> Option<&str> {
+    text.lines().find_map(|line| {
+        line.trim()
+            .strip_prefix("\\newcommand{\\pediaReferenceBookName}{")?
+            .strip_suffix('}')
+    })
+}
+
+/// The main HTML template, loaded on first use, so that we don't bother
+/// reading it if no pages need to be generated. The generated pages belong to
+/// the reference book, so its name is loaded along with the template.
+pub struct Template {
+    ident: RuntimeEntityIdent,
+    book_names_ident: RuntimeEntityIdent,
+    loaded: Option<(String, String)>,
+}
+
+impl Template {
+    pub fn new(indices: &mut IndexCollection) -> Self {
+        Template {
+            ident: RuntimeEntityIdent::new_other_file("cls/template.html", indices),
+            book_names_ident: RuntimeEntityIdent::new_other_file(
+                "cls/pedia/booknames.tex",
+                indices,
+            ),
+            loaded: None,
+        }
+    }
+
+    /// Get the text of the template and the name of the reference book.
+    fn get(&mut self, indices: &IndexCollection) -> Result<(&str, &str)> {
+        if self.loaded.is_none() {
+            let template = read_file(indices.path_for_runtime_ident(self.ident).unwrap())?;
+            let book_names_path = indices
+                .path_for_runtime_ident(self.book_names_ident)
+                .unwrap();
+            let book_names = read_file(&book_names_path)?;
+
+            let book_name = a_ok_or!(
+                reference_book_name(&book_names);
+                ["failed to find the definition of `\\pediaReferenceBookName` in `{}`", book_names_path.display()]
+            );
+
+            self.loaded = Some((template, book_name.to_owned()));
+        }
+
+        let (template, book_name) = self.loaded.as_ref().unwrap();
+        Ok((template, book_name))
+    }
+}
+
+fn read_file(path: impl AsRef<Path>) -> Result<String> {
+    let path = path.as_ref();
+    let mut text = String::new();
+
+    atry!(
+        File::open(path).and_then(|mut f| f.read_to_string(&mut text));
+        ["failed to read `{}`", path.display()]
+    );
+
+    Ok(text)
+}
+
+/// A page that we generate directly, as an operation of its own, from a single
+/// input file.
+pub struct GeneratedPage<'a> {
+    /// The identifier of the operation creating the page.
+    pub opid: DigestData,
+
+    /// A description of the page, for diagnostics.
+    pub desc: &'a str,
+
+    /// The input file from which the page content is rendered.
+    pub input: RuntimeEntityIdent,
+
+    /// The page's output file.
+    pub output: RuntimeEntityIdent,
+
+    /// The page title, as plain text.
+    pub title: &'a str,
+
+    /// The relative path from the page to the top of the output tree.
+    pub rel_top: &'a str,
+}
+
+/// Potentially emit a generated page, if its operation needs to be rerun.
+///
+/// The *render_content* callback is given the path of the page's input file,
+/// and returns the HTML content to insert into the template. The return value
+/// is true if the page was modified.
+pub fn maybe_make_page<F>(
+    page: GeneratedPage,
+    template: &mut Template,
+    render_content: F,
+    cache: &mut Cache,
+    indices: &mut IndexCollection,
+    status: &mut dyn StatusBackend,
+) -> Result<bool>
+where
+    F: FnOnce(&Path) -> Result<String>,
+{
+    let rerun_reason = atry!(
+        cache.operation_needs_rerun(&page.opid, page.desc, indices, status);
+        ["failed to probe cache for the creation of {}", page.desc]
+    );
+
+    if rerun_reason.is_none() {
+        return Ok(false);
+    }
+
+    let mut ocd = OpCacheData::new(page.opid);
+    ocd.add_input(page.input);
+    ocd.add_input(template.ident);
+    ocd.add_input(template.book_names_ident);
+
+    let input_path = indices.path_for_runtime_ident(page.input).unwrap();
+    let content = atry!(
+        render_content(&input_path);
+        ["failed to create the content of {}", page.desc]
+    );
+
+    let output = page.output;
+    let orig_digest = cache.unconditional_entity(output, indices)?.value_digest;
+
+    let mut output_stream = atry!(
+        OpOutputStream::new(output, indices);
+        ["failed to open output file {:?}", output]
+    );
+
+    let (template_text, book_name) = template.get(indices)?;
+
+    let text = render_template(
+        template_text,
+        &TemplateVars {
+            book_name,
+            title: page.title,
+            rel_top: page.rel_top,
+            content: &content,
+        },
+    );
+
+    atry!(
+        output_stream.write_all(text.as_bytes());
+        ["error writing to output {:?}", output]
+    );
+
+    let (entity, size) = atry!(
+        output_stream.close();
+        ["failed to close output file {:?}", output]
+    );
+
+    ocd.add_output_with_value(output, entity.value_digest, size);
+
+    atry!(
+        cache.finalize_operation(ocd, indices);
+        ["failed to store caching information for the creation of {}", page.desc]
+    );
+
+    Ok(entity.value_digest != orig_digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates() {
+        let template = "<title>{{ pediaTitle }}</title><h2>{{pediaBookName}}</h2>\
+            <a href=\"{{ tduxRelTop }}\">{{ other }}</a>{{ tduxContent | safe }}{{";
+
+        let vars = TemplateVars {
+            book_name: "B & B",
+            title: "{{ tduxRelTop }} <T>",
+            rel_top: "../",
+            content: "<p>{{ pediaTitle }}</p>",
+        };
+
+        assert_eq!(
+            render_template(template, &vars),
+            "<title>{{ tduxRelTop }} &lt;T&gt;</title><h2>B &amp; B</h2>\
+            <a href=\"../\">{{ other }}</a><p>{{ pediaTitle }}</p>{{"
+        );
+    }
+
+    #[test]
+    fn book_names() {
+        let text = std::fs::read_to_string("cls/pedia/booknames.tex").unwrap();
+        assert_eq!(
+            reference_book_name(&text),
+            Some("Tectonopedia: The Reference")
+        );
+        assert_eq!(reference_book_name("\\newcommand{\\other}{x}"), None);
+    }
+}
@@ -3,9 +3,10 @@
 
 #![allow(unused)]
 
+use serde::{Deserialize, Serialize};
 use sha2::Digest;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt::Write as FmtWrite,
     fs::File,
     io::{BufRead, BufReader, Read, Write},
@@ -17,23 +18,53 @@ use tectonic_status_base::{tt_error, tt_warning, StatusBackend};
 
 use crate::{
     cache::{Cache, OpCacheData},
+    failures::FailureLog,
     holey_vec::HoleyVec,
-    messages::SyncMessageBusSender,
+    messages::{AlertMessage, Message, SyncMessageBusSender},
     metadata::Metadatum,
+    metadata_snapshot::{self, InputMetadata},
     multivec::MultiVec,
-    operation::{DigestComputer, OpOutputStream, PersistEntityIdent, RuntimeEntityIdent},
-    tex_escape::encode_tex_to_string,
+    operation::{
+        DigestComputer, DigestData, OpOutputStream, PersistEntityIdent, RuntimeEntityIdent,
+    },
+    tex_escape::{encode_tex_to_string, encode_tex_url_to_string},
     InputId,
 };
 
 use string_interner::DefaultSymbol as EntryId;
 pub type IndexId = EntryId;
 
-#[derive(Debug, Default)]
+/// An input and a line number within it. A line number of zero means that the
+/// line is unknown.
+type InputLine = (InputId, u32);
+
+#[derive(Clone, Debug, Default)]
 struct Index {
     entries: StringInterner,
     locs: Vec<Option<OutputLocation>>,
     texts: Vec<Option<EntryText>>,
+    aliases: Vec<Option<EntryId>>,
+
+    /// The input, and line within it, in which each entry's location was
+    /// defined.
+    def_inputs: Vec<Option<InputLine>>,
+
+    /// The input, and line within it, in which each alias was declared.
+    alias_inputs: Vec<Option<InputLine>>,
+
+    /// For case-insensitive indices, entries that don't have definitions of
+    /// their own, mapped to the defined entries whose names differ from theirs
+    /// only in case. These are followed like aliases. Populated by
+    /// [`Self::fold_case`].
+    case_folds: HashMap<EntryId, EntryId>,
+
+    /// For indices loaded from external inventories, the absolute URLs of the
+    /// entries. Such entries don't have output locations.
+    urls: Vec<Option<String>>,
+
+    /// Whether this index is loaded from an external inventory, and so can't
+    /// be modified by the TeX inputs.
+    external: bool,
 }
 
 impl Index {
@@ -44,28 +75,34 @@ impl Index {
     }
 
     /// Ensure that the name exists in the index, and declare the location of
-    /// its definition.
+    /// its definition, made at the specified line of the specified input.
     ///
     /// The operation can fail if the name has already had its location defined,
-    /// and this definition is for a different location. In that case, the error
-    /// value is the location of the previous definition.
+    /// and this definition is for a different location. In that case, the
+    /// existing definition is retained, and the error value is the location and
+    /// input line of the previous definition.
     fn define_loc(
         &mut self,
         name: impl AsRef<str>,
         loc: OutputLocation,
-    ) -> Result<EntryId, OutputLocation> {
+        input: InputId,
+        line: u32,
+    ) -> Result<EntryId, (OutputLocation, InputLine)> {
         let entry = self.reference(name);
         let eidx = entry.to_usize();
 
         // The Err case will always be Some because no error is returned if the
         // existing value is the default.
         if let Err(Some(prev_loc)) = self.locs.ensure_holey_slot_available(eidx) {
-            if *prev_loc == loc {
-                return Err(*prev_loc);
+            if *prev_loc != loc {
+                let prev_input = self.def_inputs[eidx].unwrap();
+                return Err((*prev_loc, prev_input));
             }
         }
 
         self.locs[eidx] = Some(loc);
+        let _ = self.def_inputs.ensure_holey_slot_available(eidx);
+        self.def_inputs[eidx] = Some((input, line));
         Ok(entry)
     }
 
@@ -87,7 +124,7 @@ impl Index {
         // The Err case will always be Some because no error is returned if the
         // existing value is the default.
         if let Err(Some(prev_text)) = self.texts.ensure_holey_slot_available(eidx) {
-            if *prev_text == text {
+            if *prev_text != text {
                 return Err((prev_text.clone(), text));
             }
         }
@@ -96,6 +133,66 @@ impl Index {
         Ok(entry)
     }
 
+    /// Ensure that the name exists in the index and declare it to be an alias
+    /// of another entry, which is also ensured to exist. The declaration is
+    /// made at the specified line of the specified input.
+    ///
+    /// The operation can fail if the name has already been declared to be an
+    /// alias of a different entry. In that case, the error value is the
+    /// previous target.
+    fn define_alias(
+        &mut self,
+        name: impl AsRef<str>,
+        target: impl AsRef<str>,
+        input: InputId,
+        line: u32,
+    ) -> Result<EntryId, EntryId> {
+        let entry = self.reference(name);
+        let target = self.reference(target);
+        let eidx = entry.to_usize();
+
+        if let Err(Some(prev_target)) = self.aliases.ensure_holey_slot_available(eidx) {
+            if *prev_target != target {
+                return Err(*prev_target);
+            }
+        }
+
+        self.aliases[eidx] = Some(target);
+        let _ = self.alias_inputs.ensure_holey_slot_available(eidx);
+        self.alias_inputs[eidx] = Some((input, line));
+        Ok(entry)
+    }
+
+    /// Return whether the specified entry is an alias.
+    fn is_alias(&self, entry: EntryId) -> bool {
+        self.aliases.holey_slot_is_filled(entry.to_usize())
+    }
+
+    /// Follow any chain of aliases starting at the specified entry, returning
+    /// the entry at its end. For entries that aren't aliases, this is just the
+    /// entry itself. Returns None if the aliases form a cycle.
+    ///
+    /// Case folds are followed too, so the walk is bounded by the number of
+    /// entries rather than the number of aliases: any chain longer than that
+    /// must revisit an entry.
+    fn canonical(&self, entry: EntryId) -> Option<EntryId> {
+        let mut cur = entry;
+
+        for _ in 0..=self.entries.len() {
+            let next = self
+                .aliases
+                .get_holey_slot(cur.to_usize())
+                .or_else(|| self.case_folds.get(&cur).copied());
+
+            match next {
+                Some(next) => cur = next,
+                None => return Some(cur),
+            }
+        }
+
+        None
+    }
+
     /// Get the numeric ID associated with the given entry name, if it has been
     /// defined.
     #[inline(always)]
@@ -112,32 +209,77 @@ impl Index {
         self.entries.resolve(entry).unwrap()
     }
 
-    /// Return whether the specified entry has a definition location.
+    /// Return whether the specified entry has a definition location. Aliases
+    /// are followed.
     fn has_location(&self, entry: EntryId) -> bool {
-        self.locs.holey_slot_is_filled(entry.to_usize())
+        self.canonical(entry)
+            .map(|e| {
+                self.locs.holey_slot_is_filled(e.to_usize())
+                    || self.urls.holey_slot_is_filled(e.to_usize())
+            })
+            .unwrap_or(false)
     }
 
     /// Return whether the specified entry has a defined textualization.
+    /// Aliases are followed.
     fn has_text(&self, entry: EntryId) -> bool {
-        self.texts.holey_slot_is_filled(entry.to_usize())
+        self.canonical(entry)
+            .map(|e| self.texts.holey_slot_is_filled(e.to_usize()))
+            .unwrap_or(false)
     }
 
     /// Return the definition location of the specified entry, if it has been
-    /// defined.
+    /// defined. Aliases are followed.
     fn get_location(&self, entry: EntryId) -> Option<OutputLocation> {
-        self.locs.get_holey_slot(entry.to_usize())
+        self.canonical(entry)
+            .and_then(|e| self.locs.get_holey_slot(e.to_usize()))
     }
 
     /// Return the definition text of the specified entry, if it has been
-    /// defined.
+    /// defined. Aliases are followed.
     fn get_text(&self, entry: EntryId) -> Option<EntryText> {
-        self.texts.get_holey_slot(entry.to_usize())
+        self.canonical(entry)
+            .and_then(|e| self.texts.get_holey_slot(e.to_usize()))
     }
 
     fn iter(&self) -> impl IntoIterator<Item = (EntryId, &str)> {
         self.entries.into_iter()
     }
 
+    /// Return whether the specified entry has a location, text, or URL of its
+    /// own, or is an alias.
+    fn is_defined_itself(&self, entry: EntryId) -> bool {
+        let e = entry.to_usize();
+        self.locs.holey_slot_is_filled(e)
+            || self.texts.holey_slot_is_filled(e)
+            || self.urls.holey_slot_is_filled(e)
+            || self.aliases.holey_slot_is_filled(e)
+    }
+
+    /// Make entries that aren't defined resolve to defined entries whose names
+    /// differ from theirs only in case. If several defined entries fold to
+    /// the same name, the first one to have been referenced wins.
+    fn fold_case(&mut self) {
+        let mut defined = HashMap::new();
+        let mut undefined = Vec::new();
+
+        for (id, name) in self.iter() {
+            if self.is_defined_itself(id) {
+                defined.entry(name.to_lowercase()).or_insert(id);
+            } else {
+                undefined.push((id, name.to_lowercase()));
+            }
+        }
+
+        self.case_folds.clear();
+
+        for (id, folded) in undefined {
+            if let Some(target) = defined.get(&folded) {
+                self.case_folds.insert(id, *target);
+            }
+        }
+    }
+
     /// Serialize this index in CSV format.
     ///
     /// We sort by entry name to hopefully keep the outputs reproducible.
@@ -162,8 +304,7 @@ impl Index {
 
         for (entry_id, entry_text) in all.drain(..) {
             let (loc_output, loc_fragment) = self
-                .locs
-                .get_holey_slot(entry_id.to_usize())
+                .get_location(entry_id)
                 .map(|loc| {
                     (
                         outputs_index.resolve(loc.output),
@@ -173,8 +314,7 @@ impl Index {
                 .unwrap_or(("", ""));
 
             let (text_tex, text_plain) = self
-                .texts
-                .get_holey_slot(entry_id.to_usize())
+                .get_text(entry_id)
                 .map(|etext| (etext.tex, etext.plain))
                 .unwrap_or((String::new(), String::new()));
 
@@ -186,7 +326,92 @@ impl Index {
     }
 }
 
-#[derive(Debug)]
+/// The settings of a user index, from its definition file.
+#[derive(Clone, Debug, Default)]
+struct IndexConfig {
+    /// The path of the definition file, relative to the project root.
+    path: String,
+
+    /// The human-readable title of the index, if it has one.
+    title: Option<String>,
+
+    /// Whether every entry with a location must also have text.
+    require_text: bool,
+
+    /// Whether references match entry names case-insensitively.
+    case_insensitive: bool,
+
+    /// The prefix that the output paths of the entries' locations must have,
+    /// such as `e/`.
+    url_prefix: Option<String>,
+
+    /// Whether to warn about entries that are defined but never referenced.
+    warn_unreferenced: bool,
+
+    /// The title of the A–Z listing page of the index, if it should have one.
+    listing_title: Option<String>,
+}
+
+impl IndexConfig {
+    /// Validate the settings parsed from the definition file at `path`.
+    fn new(path: String, rec: &syntax::Index) -> Result<Self> {
+        let h = &rec.index;
+
+        if let Some(title) = h.title.as_ref() {
+            ensure!(
+                !title.trim().is_empty(),
+                "the index `title` may not be empty"
+            );
+        }
+
+        if let Some(prefix) = h.url_prefix.as_ref() {
+            ensure!(
+                rec.inventory.is_none(),
+                "an index loaded from an external inventory can't have a `url_prefix`"
+            );
+            ensure!(
+                prefix.ends_with('/')
+                    && !prefix.starts_with('/')
+                    && !prefix.split('/').any(|c| c == "." || c == ".."),
+                "the `url_prefix` `{}` must be a relative path ending in `/`, without `.` or `..` components",
+                prefix
+            );
+        }
+
+        let listing_title = match rec.listing.as_ref() {
+            Some(listing) => {
+                ensure!(
+                    rec.inventory.is_none(),
+                    "an index loaded from an external inventory can't have a listing page"
+                );
+
+                let title = a_ok_or!(
+                    listing.title.as_ref().or(h.title.as_ref());
+                    ["the listing page needs a `title`, either in `[listing]` or in `[index]`"]
+                );
+                ensure!(
+                    !title.trim().is_empty(),
+                    "the listing page `title` may not be empty"
+                );
+                Some(title.clone())
+            }
+
+            None => None,
+        };
+
+        Ok(IndexConfig {
+            path,
+            title: h.title.clone(),
+            require_text: h.require_text,
+            case_insensitive: h.case_insensitive,
+            url_prefix: h.url_prefix.clone(),
+            warn_unreferenced: h.warn_unreferenced,
+            listing_title,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
 pub struct IndexCollection {
     indices: Vec<Index>,
 
@@ -197,11 +422,52 @@ pub struct IndexCollection {
     /// EntryIds.
     refs: MultiVec<IndexRef>,
 
+    /// The "backlinks" of each index entry: the locations in the output that
+    /// refer to it. Keyed by the index and entry being referred to. Once
+    /// [`Self::finalize_backlinks`] has been called, each list is sorted and
+    /// deduplicated.
+    backlinks: HashMap<(IndexId, EntryId), Vec<OutputLocation>>,
+
+    /// For each output that has an index entry defined at its very top, that
+    /// entry. We use these entries' texts to label backlinks. Populated by
+    /// [`Self::finalize_backlinks`].
+    output_titles: HashMap<EntryId, (IndexId, EntryId)>,
+
+    /// The outputs declared by the TeX inputs, mapped to the first input that
+    /// declared each one, and the line of the declaration.
+    output_sources: HashMap<EntryId, InputLine>,
+
+    /// Declarations of outputs that were already declared by other inputs.
+    duplicate_outputs: HashMap<EntryId, Vec<InputLine>>,
+
+    /// Declarations of outputs with invalid paths: the declaring inputs and
+    /// lines, the paths, and the problems with them.
+    invalid_outputs: Vec<(InputLine, String, String)>,
+
+    /// The outputs tagged with each tag.
+    tags: HashMap<String, Vec<EntryId>>,
+
+    /// The paths of the inventory files from which external indices were
+    /// loaded, relative to the project root.
+    inventories: HashMap<IndexId, String>,
+
+    /// The settings of the user indices, from their definition files.
+    configs: HashMap<IndexId, IndexConfig>,
+
+    /// Definitions of index entry locations that conflicted with earlier ones.
+    /// The values are the inputs, input lines, and locations of the later
+    /// definitions.
+    duplicate_defs: HashMap<(IndexId, EntryId), Vec<(InputLine, OutputLocation)>>,
+
     /// The tree root doesn't have to do with the indices as used in the text
     /// processing, but we also use the indices to manage input and output
     /// paths used by the build system, so it's convenient to have the root
     /// saved here.
     root: PathBuf,
+
+    /// Likewise, the names of the environment variables used by the build
+    /// system. These aren't paths, so they don't get an index of their own.
+    env_var_names: StringInterner,
 }
 
 pub const INDEX_OF_INDICES_NAME: &str = "ioi";
@@ -226,7 +492,17 @@ impl IndexCollection {
         let mut inst = IndexCollection {
             indices: vec![Index::default()],
             refs: Default::default(),
+            backlinks: Default::default(),
+            output_titles: Default::default(),
+            output_sources: Default::default(),
+            duplicate_defs: Default::default(),
+            duplicate_outputs: Default::default(),
+            invalid_outputs: Default::default(),
+            inventories: Default::default(),
+            configs: Default::default(),
+            tags: Default::default(),
             root,
+            env_var_names: Default::default(),
         };
 
         let id = inst.indices[INDEX_OF_INDICES_INDEX].reference(INDEX_OF_INDICES_NAME);
@@ -263,6 +539,21 @@ impl IndexCollection {
         Ok(id)
     }
 
+    /// Get the names of the user-defined indices, sorted. Indices loaded from
+    /// external inventories are not included.
+    pub fn user_index_names(&self) -> Vec<&str> {
+        let mut names: Vec<_> = self.indices[INDEX_OF_INDICES_INDEX]
+            .iter()
+            .into_iter()
+            .filter(|(id, _)| {
+                id.to_usize() > FRAGMENTS_INDEX_INDEX && !self.indices[id.to_usize()].external
+            })
+            .map(|t| t.1)
+            .collect();
+        names.sort();
+        names
+    }
+
     /// Convert an index name into its IndexId. The conversion can fail if the
     /// index in question was never declared.
     pub fn get_index(&self, name: impl AsRef<str>) -> Result<IndexId> {
@@ -304,25 +595,34 @@ impl IndexCollection {
         Ok((index, entry))
     }
 
+    /// Define the location of an index entry, as made at the specified line of
+    /// the specified input.
+    ///
+    /// If the entry already has a different location, the first definition is
+    /// retained and the new one is recorded as a duplicate, to be reported by
+    /// [`Self::validate_definitions`].
     pub fn define_loc_by_id(
         &mut self,
         index: IndexId,
         entry: impl AsRef<str>,
         loc: OutputLocation,
-    ) -> Result<EntryId> {
-        let entry = entry.as_ref();
-
-        self.indices[index.to_usize()].define_loc(entry, loc).map_err(|prev_loc|
-            anyhow!(
-                "redefinition of entry location `{}` in index `{}`; previous was `{}{}`, new is `{}{}`",
-                entry,
-                self.indices[INDEX_OF_INDICES_INDEX].resolve(index),
-                self.indices[OUTPUTS_INDEX_INDEX].resolve(prev_loc.output),
-                self.indices[FRAGMENTS_INDEX_INDEX].resolve(prev_loc.fragment),
-                self.indices[OUTPUTS_INDEX_INDEX].resolve(loc.output),
-                self.indices[FRAGMENTS_INDEX_INDEX].resolve(loc.fragment),
-            )
-        )
+        input: InputId,
+        line: u32,
+    ) -> EntryId {
+        let idx = &mut self.indices[index.to_usize()];
+
+        match idx.define_loc(entry.as_ref(), loc, input, line) {
+            Ok(entry) => entry,
+
+            Err(_) => {
+                let entry = idx.reference(entry);
+                self.duplicate_defs
+                    .entry((index, entry))
+                    .or_default()
+                    .push(((input, line), loc));
+                entry
+            }
+        }
     }
 
     pub fn define_loc(
@@ -330,9 +630,12 @@ impl IndexCollection {
         index: impl AsRef<str>,
         entry: impl AsRef<str>,
         loc: OutputLocation,
+        input: InputId,
+        line: u32,
     ) -> Result<EntryId> {
         let id = self.get_index(index)?;
-        self.define_loc_by_id(id, entry, loc)
+        self.ensure_not_external(id)?;
+        Ok(self.define_loc_by_id(id, entry, loc, input, line))
     }
 
     pub fn define_text(
@@ -342,27 +645,214 @@ impl IndexCollection {
         text: EntryText,
     ) -> Result<EntryId> {
         let index = self.get_index(index)?;
+        self.ensure_not_external(index)?;
         let entry = entry.as_ref();
+        let idx = &mut self.indices[index.to_usize()];
+
+        // If the entry's location was defined more than once, it's expected
+        // that its text will have been too. That problem will be reported by
+        // `validate_definitions()`, so we don't need to complain here.
+        if let Some(e) = idx.get(entry) {
+            if self.duplicate_defs.contains_key(&(index, e)) {
+                return Ok(e);
+            }
+        }
+
+        idx.define_text(entry, text).map_err(|(prev_text, text)| {
+            let (prev_ex, new_ex) = if prev_text.tex != text.tex {
+                (&prev_text.tex, &text.tex)
+            } else {
+                (&prev_text.plain, &text.plain)
+            };
+
+            anyhow!(
+                "redefinition of entry text `{}` in index {}; previous was `{}`, new is `{}`",
+                entry,
+                self.index_label(index),
+                prev_ex,
+                new_ex
+            )
+        })
+    }
+
+    pub fn define_alias(
+        &mut self,
+        index: impl AsRef<str>,
+        alias: impl AsRef<str>,
+        target: impl AsRef<str>,
+        input: InputId,
+        line: u32,
+    ) -> Result<EntryId> {
+        let index = self.get_index(index)?;
+        self.ensure_not_external(index)?;
+        let alias = alias.as_ref();
+        let target = target.as_ref();
 
         self.indices[index.to_usize()]
-            .define_text(entry, text)
-            .map_err(|(prev_text, text)| {
-                let (prev_ex, new_ex) = if prev_text.tex != text.tex {
-                    (&prev_text.tex, &text.tex)
-                } else {
-                    (&prev_text.plain, &text.plain)
-                };
+            .define_alias(alias, target, input, line)
+            .map_err(|prev_target| {
+                let idx = &self.indices[index.to_usize()];
 
                 anyhow!(
-                    "redefinition of entry text `{}` in index `{}`; previous was `{}`, new is `{}`",
-                    entry,
-                    self.indices[INDEX_OF_INDICES_INDEX].resolve(index),
-                    prev_ex,
-                    new_ex
+                    "redefinition of alias `{}` in index {}; previous target was `{}`, new is `{}`",
+                    alias,
+                    self.index_label(index),
+                    idx.resolve(prev_target),
+                    target
                 )
             })
     }
 
+    fn ensure_not_external(&self, index: IndexId) -> Result<()> {
+        ensure!(
+            !self.indices[index.to_usize()].external,
+            "index {} is loaded from an external inventory and cannot be modified",
+            self.index_label(index)
+        );
+        Ok(())
+    }
+
+    /// Get the paths of all of the external inventory files, relative to the
+    /// project root, sorted.
+    pub fn inventory_paths(&self) -> Vec<&str> {
+        let mut paths: Vec<_> = self.inventories.values().map(|p| p.as_str()).collect();
+        paths.sort();
+        paths
+    }
+
+    /// Get the paths of the external inventory files used by the references
+    /// of the specified input, relative to the project root, sorted.
+    pub fn inventory_paths_for_input(&self, input: InputId) -> Vec<&str> {
+        let mut paths: Vec<_> = self
+            .refs
+            .lookup(input.to_usize())
+            .into_iter()
+            .flatten()
+            .filter_map(|r| self.inventories.get(&r.index))
+            .map(|p| p.as_str())
+            .collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    /// Get the names of the user indices that should have A–Z listing pages,
+    /// along with the pages' titles, sorted by index name.
+    pub fn listings(&self) -> Vec<(&str, &str)> {
+        let ioi = &self.indices[INDEX_OF_INDICES_INDEX];
+        let mut listings: Vec<_> = self
+            .configs
+            .iter()
+            .filter_map(|(id, cfg)| Some((ioi.resolve(*id), cfg.listing_title.as_deref()?)))
+            .collect();
+        listings.sort();
+        listings
+    }
+
+    /// Get the human-readable title of a user index, if it has one.
+    pub fn index_title(&self, index: IndexId) -> Option<&str> {
+        self.configs.get(&index)?.title.as_deref()
+    }
+
+    /// Describe an index for humans: its name, followed by its title if it has
+    /// one.
+    fn index_label(&self, index: IndexId) -> String {
+        let name = self.indices[INDEX_OF_INDICES_INDEX].resolve(index);
+
+        match self.index_title(index) {
+            Some(title) => format!("`{}` ({})", name, title),
+            None => format!("`{}`", name),
+        }
+    }
+
+    /// Get the paths of the definition files of the user indices, relative to
+    /// the project root, sorted.
+    pub fn config_paths(&self) -> Vec<&str> {
+        let mut paths: Vec<_> = self.configs.values().map(|c| c.path.as_str()).collect();
+        paths.sort();
+        paths
+    }
+
+    /// Apply case-insensitive matching to the references of the indices that
+    /// are configured for it. This should be done after all of the metadata
+    /// have been loaded, since an entry may be referenced before it is
+    /// defined.
+    pub fn fold_case(&mut self) {
+        for (index, cfg) in &self.configs {
+            if cfg.case_insensitive {
+                self.indices[index.to_usize()].fold_case();
+            }
+        }
+    }
+
+    /// Record that an output is tagged with the specified tag.
+    pub fn log_tag(&mut self, tag: impl Into<String>, output: EntryId) {
+        self.tags.entry(tag.into()).or_default().push(output);
+    }
+
+    /// Get the names of all of the tags used in the outputs, sorted.
+    pub fn tag_names(&self) -> Vec<&str> {
+        let mut names: Vec<_> = self.tags.keys().map(|t| t.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    /// Get the names of the tags applied to the outputs of the specified
+    /// inputs.
+    pub fn tags_of_inputs(&self, inputs: &HashSet<InputId>) -> HashSet<String> {
+        self.tags
+            .iter()
+            .filter(|(_, outputs)| {
+                outputs
+                    .iter()
+                    .any(|o| inputs.contains(&self.output_sources[o].0))
+            })
+            .map(|(tag, _)| tag.clone())
+            .collect()
+    }
+
+    /// Get the names of the indices in which the specified inputs define the
+    /// locations of entries.
+    pub fn indices_defined_in_inputs(&self, inputs: &HashSet<InputId>) -> HashSet<String> {
+        let ioi = &self.indices[INDEX_OF_INDICES_INDEX];
+
+        self.indices
+            .iter()
+            .enumerate()
+            .filter(|(_, idx)| {
+                idx.def_inputs
+                    .iter()
+                    .flatten()
+                    .any(|(input, _)| inputs.contains(input))
+            })
+            .filter_map(|(i, _)| IndexId::try_from_usize(i))
+            .map(|id| ioi.resolve(id).to_owned())
+            .collect()
+    }
+
+    /// Record that an input declared an output.
+    ///
+    /// An input may declare the same output more than once, but if the output
+    /// was declared by a different input, the conflict is logged, to be
+    /// reported by [`Self::validate_outputs`].
+    pub fn log_output_source(&mut self, output: EntryId, input: InputId, line: u32) {
+        let first = *self.output_sources.entry(output).or_insert((input, line));
+
+        if first.0 != input {
+            self.duplicate_outputs
+                .entry(output)
+                .or_default()
+                .push((input, line));
+        }
+    }
+
+    /// Record that an input declared an output with an invalid path, to be
+    /// reported by [`Self::validate_outputs`].
+    pub fn log_invalid_output(&mut self, input: InputId, line: u32, path: &str, problem: Error) {
+        self.invalid_outputs
+            .push(((input, line), path.to_owned(), problem.to_string()));
+    }
+
     pub fn resolve_by_id(&self, index: IndexId, entry: EntryId) -> &str {
         self.indices[index.to_usize()].resolve(entry)
     }
@@ -380,8 +870,173 @@ impl IndexCollection {
         self.refs.add_extend(input.to_usize(), refs)
     }
 
+    /// Record that the specified output location refers to an index entry.
+    pub fn log_backlink(&mut self, target: (IndexId, EntryId), from: OutputLocation) {
+        self.backlinks.entry(target).or_default().push(from);
+    }
+
+    /// Tidy up the backlink information once all of the metadata have been
+    /// loaded.
+    ///
+    /// This sorts the backlinks by output path and fragment, so that they're
+    /// reproducible, and determines the labels that should be used for each
+    /// referring output. The outputs associated with each tag are sorted too.
+    pub fn finalize_backlinks(&mut self) {
+        // References to aliases count as references to their targets.
+
+        let aliased: Vec<_> = self
+            .backlinks
+            .keys()
+            .filter_map(|&(i, e)| {
+                let c = self.indices[i.to_usize()].canonical(e)?;
+                if c != e {
+                    Some(((i, e), (i, c)))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        for (alias, target) in aliased {
+            let mut locs = self.backlinks.remove(&alias).unwrap();
+            self.backlinks.entry(target).or_default().append(&mut locs);
+        }
+
+        let outputs = &self.indices[OUTPUTS_INDEX_INDEX];
+        let fragments = &self.indices[FRAGMENTS_INDEX_INDEX];
+
+        for locs in self.backlinks.values_mut() {
+            locs.sort_by_key(|l| (outputs.resolve(l.output), fragments.resolve(l.fragment)));
+            locs.dedup();
+        }
+
+        // While we're at it, tidy up the tags too.
+
+        for members in self.tags.values_mut() {
+            members.sort_by_key(|o| outputs.resolve(*o));
+            members.dedup();
+        }
+
+        // The label of an output is the text of the entry that is defined at
+        // its top. In case there are several, we go through the user indices
+        // in order of name, so that the choice is deterministic.
+
+        self.output_titles.clear();
+
+        let empty_frag = match fragments.get("") {
+            Some(f) => f,
+            None => return,
+        };
+
+        let mut user_indices: Vec<_> = self.indices[INDEX_OF_INDICES_INDEX]
+            .iter()
+            .into_iter()
+            .filter(|(id, _)| id.to_usize() > FRAGMENTS_INDEX_INDEX)
+            .collect();
+        user_indices.sort_by_key(|t| t.1);
+
+        for (index_id, _) in user_indices {
+            let index = &self.indices[index_id.to_usize()];
+
+            for (entry_id, _) in index.iter() {
+                if !index.has_text(entry_id) {
+                    continue;
+                }
+
+                if let Some(loc) = index.get_location(entry_id) {
+                    if loc.fragment == empty_frag {
+                        self.output_titles
+                            .entry(loc.output)
+                            .or_insert((index_id, entry_id));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Get a plain-text title for an output.
+    ///
+    /// This is the plain text of the entry defined at the top of the output, if
+    /// there is one, and the output path otherwise. It is only meaningful after
+    /// [`Self::finalize_backlinks`] has been called.
+    fn output_title_plain(&self, output: EntryId) -> String {
+        match self.output_titles.get(&output) {
+            Some((i, e)) => self.indices[i.to_usize()].get_text(*e).unwrap().plain,
+            None => self.indices[OUTPUTS_INDEX_INDEX].resolve(output).to_owned(),
+        }
+    }
+
+    /// Serialize the list of tags in CSV format, with the number of outputs
+    /// having each tag.
+    fn write_tags<W: Write>(&self, dest: W) -> Result<W> {
+        let mut w = csv::Writer::from_writer(dest);
+
+        w.write_record(["tag", "n_outputs"])?;
+
+        for tag in self.tag_names() {
+            let n = self.tags[tag].len().to_string();
+            w.write_record([tag, &n])?;
+        }
+
+        Ok(w.into_inner().map_err(|e| e.into_error())?)
+    }
+
+    /// Serialize the outputs having the specified tag in CSV format. They are
+    /// sorted by path.
+    fn write_tag_members<W: Write>(&self, dest: W, tag: &str) -> Result<W> {
+        let outputs = &self.indices[OUTPUTS_INDEX_INDEX];
+        let mut w = csv::Writer::from_writer(dest);
+
+        w.write_record(["output", "url", "title"])?;
+
+        for o in &self.tags[tag] {
+            let url = self.location_url(OutputLocation::new(
+                *o,
+                self.indices[FRAGMENTS_INDEX_INDEX].get("").unwrap(),
+            ));
+            w.write_record([outputs.resolve(*o), &url, &self.output_title_plain(*o)])?;
+        }
+
+        Ok(w.into_inner().map_err(|e| e.into_error())?)
+    }
+
+    /// Get the URL associated with an output location, relative to the top of
+    /// the output tree.
+    fn location_url(&self, loc: OutputLocation) -> String {
+        let o = self.indices[OUTPUTS_INDEX_INDEX].resolve(loc.output);
+        let f = self.indices[FRAGMENTS_INDEX_INDEX].resolve(loc.fragment);
+
+        let o = if o.ends_with("/index.html") {
+            &o[..o.len() - 10]
+        } else {
+            o
+        };
+
+        format!("{}{}", o, f)
+    }
+
+    /// Get the URL of an index entry. For most entries, this is relative to the
+    /// top of the output tree; for entries in external indices, it is absolute.
+    fn entry_url(&self, index: IndexId, entry: EntryId) -> Option<String> {
+        let idx = &self.indices[index.to_usize()];
+        let entry = idx.canonical(entry)?;
+
+        if let Some(url) = idx.urls.get_holey_slot(entry.to_usize()) {
+            return Some(url);
+        }
+
+        idx.get_location(entry).map(|loc| self.location_url(loc))
+    }
+
     /// Validate all of the cross-references.
-    pub fn validate_references(&self, bus_tx: &mut SyncMessageBusSender) -> Result<()> {
+    ///
+    /// Inputs with unresolved references are recorded in *failures*. In "keep
+    /// going" mode, they aren't treated as an error.
+    pub fn validate_references(
+        &self,
+        failures: &FailureLog,
+        bus_tx: &mut SyncMessageBusSender,
+    ) -> Result<()> {
         // Multiple inputs might reference the same entry, of course. We need to
         // keep track of references for each input, though, to know which
         // resolutions to provide in pass 2, and checking these resolutions
@@ -404,9 +1059,11 @@ impl IndexCollection {
                     let e = self.indices[entry.index.to_usize()].resolve(entry.entry);
                     bus_tx.file_error(
                         input_name,
+                        entry.line,
                         format!("reference to location of index entry `{}:{}` that does not have one defined", i, e),
-                        None
+                        self.suggest_entries(entry.index, e, |idx, eid| idx.has_location(eid)),
                     );
+                    failures.fail(input_name);
                     n_failures += 1;
                 }
 
@@ -417,9 +1074,11 @@ impl IndexCollection {
                     let e = self.indices[entry.index.to_usize()].resolve(entry.entry);
                     bus_tx.file_error(
                         input_name,
+                        entry.line,
                         format!("reference to text of index entry `{}:{}` that does not have it defined", i, e),
-                        None
+                        self.suggest_entries(entry.index, e, |idx, eid| idx.has_text(eid)),
                     );
+                    failures.fail(input_name);
                     n_failures += 1;
                 }
             }
@@ -427,58 +1086,642 @@ impl IndexCollection {
 
         match n_failures {
             0 => Ok(()),
+            _ if failures.keep_going() => Ok(()),
             1 => Err(anyhow!("1 unresolved index reference")),
             n => Err(anyhow!("{} unresolved index references", n)),
         }
     }
 
-    /// Get a user-friendly(ish) summary of the indexing data.
-    pub fn index_summary(&self) -> String {
-        let n_indices = self.indices.len();
+    /// Check for index entries whose locations were defined more than once.
+    ///
+    /// Each duplicate definition is reported as an error associated with the
+    /// input that made it, naming the input and location of the original
+    /// definition. All of the duplicated entries are then listed together in a
+    /// single summary.
+    ///
+    /// All of the inputs defining a duplicated entry are recorded in
+    /// *failures*. In "keep going" mode, they aren't treated as an error.
+    pub fn validate_definitions(
+        &self,
+        failures: &FailureLog,
+        bus_tx: &mut SyncMessageBusSender,
+    ) -> Result<()> {
+        if self.duplicate_defs.is_empty() {
+            return Ok(());
+        }
 
-        let mut n_entries = 0;
+        let ioi = &self.indices[INDEX_OF_INDICES_INDEX];
+        let inputs = &self.indices[INPUTS_INDEX_INDEX];
+
+        let mut dups: Vec<_> = self
+            .duplicate_defs
+            .iter()
+            .map(|((i, e), defs)| {
+                (
+                    ioi.resolve(*i),
+                    self.indices[i.to_usize()].resolve(*e),
+                    (*i, *e),
+                    defs,
+                )
+            })
+            .collect();
+        dups.sort_by_key(|t| (t.0, t.1));
+
+        let mut summary = Vec::new();
+
+        for (iname, ename, (i, e), defs) in dups {
+            let idx = &self.indices[i.to_usize()];
+            let first_loc = idx.locs.get_holey_slot(e.to_usize()).unwrap();
+            let (first_input, first_line) = idx.def_inputs.get_holey_slot(e.to_usize()).unwrap();
+            let first_input = inputs.resolve(first_input);
+            let mut all_inputs = vec![first_input];
+            failures.fail(first_input);
+
+            for ((input, line), loc) in defs {
+                let input = inputs.resolve(*input);
+                all_inputs.push(input);
+                failures.fail(input);
+
+                bus_tx.file_error(
+                    input,
+                    *line,
+                    format!(
+                        "index entry `{}:{}` is defined twice: in `{}` at `{}`, and in `{}` at `{}`",
+                        iname,
+                        ename,
+                        located(first_input, first_line),
+                        self.location_url(first_loc),
+                        located(input, *line),
+                        self.location_url(*loc),
+                    ),
+                    Vec::new(),
+                );
+            }
 
-        for idx in &self.indices {
-            n_entries += idx.entries.len();
+            all_inputs.dedup();
+            summary.push(format!("`{}:{}`: {}", iname, ename, all_inputs.join(", ")));
         }
 
-        format!("{n_entries} entries in {n_indices} indices")
+        let n = summary.len();
+
+        bus_tx.post(Message::Error(AlertMessage {
+            file: None,
+            line: None,
+            message: format!("index entries defined in more than one place ({}):", n),
+            context: summary,
+        }));
+
+        match n {
+            _ if failures.keep_going() => Ok(()),
+            1 => Err(anyhow!("1 index entry defined more than once")),
+            n => Err(anyhow!("{} index entries defined more than once", n)),
+        }
     }
 
-    pub fn get_resolved_reference_tex(&self, input: InputId) -> String {
-        // Because we have validated cross-references, we can unwrap everything
-        // here without worrying about missing values.
-        let refs = self.refs.lookup(input.to_usize()).unwrap();
-        let mut tex = String::new();
+    /// Check for outputs that were declared by more than one input, or with
+    /// invalid paths.
+    ///
+    /// The pass-2 jobs of inputs declaring the same output would race to write
+    /// the same file, so this is a hard error. Each conflicting output is
+    /// reported as an error associated with the input that first declared it,
+    /// listing all of its declarations. Invalid paths are reported at their
+    /// declarations.
+    ///
+    /// All of the inputs declaring a conflicting output or an invalid path are
+    /// recorded in *failures*, so that none of them are run in pass 2. In "keep
+    /// going" mode, they aren't treated as an error.
+    pub fn validate_outputs(
+        &self,
+        failures: &FailureLog,
+        bus_tx: &mut SyncMessageBusSender,
+    ) -> Result<()> {
+        if self.duplicate_outputs.is_empty() && self.invalid_outputs.is_empty() {
+            return Ok(());
+        }
 
-        for entry in refs {
-            let iname = self.indices[INDEX_OF_INDICES_INDEX].resolve(entry.index);
-            let iindex = entry.index.to_usize();
-            let ename = self.indices[iindex].resolve(entry.entry);
-            let f = entry.flags;
+        let outputs = &self.indices[OUTPUTS_INDEX_INDEX];
+        let inputs = &self.indices[INPUTS_INDEX_INDEX];
 
-            if (f & IndexRefFlag::NeedsLoc as u8) != 0 {
-                let loc = self.indices[iindex].get_location(entry.entry).unwrap();
-                let o = self.indices[OUTPUTS_INDEX_INDEX].resolve(loc.output);
-                let f = self.indices[FRAGMENTS_INDEX_INDEX].resolve(loc.fragment);
+        for ((input, line), path, problem) in &self.invalid_outputs {
+            let input = inputs.resolve(*input);
+            failures.fail(input);
 
-                let o = if o.ends_with("/index.html") {
-                    &o[..o.len() - 10]
-                } else {
-                    o
-                };
+            bus_tx.file_error(
+                input,
+                *line,
+                format!("invalid output path `{}`: {}", path, problem),
+                Vec::new(),
+            );
+        }
 
-                writeln!(
-                    tex,
-                    r"\expandafter\def\csname pedia resolve**{}**{}**loc\endcsname{{{}{}}}",
-                    iname, ename, o, f
-                )
-                .unwrap();
+        let mut dups: Vec<_> = self
+            .duplicate_outputs
+            .iter()
+            .map(|(o, decls)| (outputs.resolve(*o), self.output_sources[o], decls))
+            .collect();
+        dups.sort_by_key(|t| t.0);
+
+        for (path, (first_input, first_line), decls) in &dups {
+            let first_input = inputs.resolve(*first_input);
+            failures.fail(first_input);
+
+            let mut declarers = vec![format!("`{}`", located(first_input, *first_line))];
+            let mut n_inputs = 1;
+
+            for (i, (input, line)) in decls.iter().enumerate() {
+                if !decls[..i].iter().any(|(prev, _)| prev == input) {
+                    n_inputs += 1;
+                }
+
+                let input = inputs.resolve(*input);
+                failures.fail(input);
+                declarers.push(format!("`{}`", located(input, *line)));
             }
 
-            if (f & IndexRefFlag::NeedsText as u8) != 0 {
-                let text = self.indices[iindex].get_text(entry.entry).unwrap();
-                writeln!(
+            bus_tx.file_error(
+                first_input,
+                *first_line,
+                format!(
+                    "output `{}` is declared by {} inputs: {}",
+                    path,
+                    n_inputs,
+                    declarers.join(", "),
+                ),
+                Vec::new(),
+            );
+        }
+
+        if failures.keep_going() {
+            return Ok(());
+        }
+
+        let mut problems = Vec::new();
+
+        match self.invalid_outputs.len() {
+            0 => {}
+            1 => problems.push("1 invalid output path".to_owned()),
+            n => problems.push(format!("{} invalid output paths", n)),
+        }
+
+        match dups.len() {
+            0 => {}
+            1 => problems.push("1 output declared by more than one input".to_owned()),
+            n => problems.push(format!("{} outputs declared by more than one input", n)),
+        }
+
+        Err(anyhow!("{}", problems.join(" and ")))
+    }
+
+    /// Validate the entry aliases.
+    ///
+    /// Aliases may not form cycles, and an entry that is an alias may not
+    /// have a location or text of its own.
+    ///
+    /// Inputs declaring invalid aliases are recorded in *failures*. In "keep
+    /// going" mode, they aren't treated as an error.
+    pub fn validate_aliases(
+        &self,
+        failures: &FailureLog,
+        bus_tx: &mut SyncMessageBusSender,
+    ) -> Result<()> {
+        let inputs = &self.indices[INPUTS_INDEX_INDEX];
+        let mut n_failures = 0;
+
+        for (index_id, index_name) in self.indices[INDEX_OF_INDICES_INDEX].iter() {
+            let idx = &self.indices[index_id.to_usize()];
+
+            for (entry_id, entry_name) in idx.iter() {
+                if !idx.is_alias(entry_id) {
+                    continue;
+                }
+
+                // Aliases are always declared by some input.
+                let (input, line) = idx
+                    .alias_inputs
+                    .get_holey_slot(entry_id.to_usize())
+                    .unwrap();
+                let input = inputs.resolve(input);
+
+                if idx.canonical(entry_id).is_none() {
+                    bus_tx.file_error(
+                        input,
+                        line,
+                        format!(
+                            "alias `{}:{}` is part of a cycle of aliases",
+                            index_name, entry_name
+                        ),
+                        Vec::new(),
+                    );
+                    failures.fail(input);
+                    n_failures += 1;
+                } else if idx.locs.holey_slot_is_filled(entry_id.to_usize())
+                    || idx.texts.holey_slot_is_filled(entry_id.to_usize())
+                {
+                    let mut context = Vec::new();
+
+                    if let Some((def_input, def_line)) =
+                        idx.def_inputs.get_holey_slot(entry_id.to_usize())
+                    {
+                        context.push(format!(
+                            "it is defined at {}",
+                            located(inputs.resolve(def_input), def_line)
+                        ));
+                    }
+
+                    bus_tx.file_error(
+                        input,
+                        line,
+                        format!(
+                            "index entry `{}:{}` is declared to be an alias, but is also defined itself",
+                            index_name, entry_name
+                        ),
+                        context,
+                    );
+                    failures.fail(input);
+                    n_failures += 1;
+                }
+            }
+        }
+
+        match n_failures {
+            0 => Ok(()),
+            _ if failures.keep_going() => Ok(()),
+            1 => Err(anyhow!("1 invalid index alias")),
+            n => Err(anyhow!("{} invalid index aliases", n)),
+        }
+    }
+
+    /// Check the index entries against the settings of their indices.
+    ///
+    /// Violations of the `require_text` and `url_prefix` settings are errors,
+    /// reported at the definitions of the offending entries. Entries that are
+    /// never referenced yield warnings if `warn_unreferenced` is set.
+    ///
+    /// Inputs defining offending entries are recorded in *failures*. In "keep
+    /// going" mode, they aren't treated as an error.
+    pub fn validate_settings(
+        &self,
+        failures: &FailureLog,
+        bus_tx: &mut SyncMessageBusSender,
+    ) -> Result<()> {
+        let ioi = &self.indices[INDEX_OF_INDICES_INDEX];
+        let inputs = &self.indices[INPUTS_INDEX_INDEX];
+        let outputs = &self.indices[OUTPUTS_INDEX_INDEX];
+        let mut n_failures = 0;
+
+        // Gather all of the entries that are referenced, following aliases.
+
+        let mut referenced = HashSet::new();
+
+        for (input_id, _) in inputs.iter() {
+            for r in self.refs.lookup(input_id.to_usize()).into_iter().flatten() {
+                referenced.insert((r.index, r.entry));
+
+                if let Some(c) = self.indices[r.index.to_usize()].canonical(r.entry) {
+                    referenced.insert((r.index, c));
+                }
+            }
+        }
+
+        let mut configs: Vec<_> = self.configs.iter().collect();
+        configs.sort_by_key(|(id, _)| ioi.resolve(**id));
+
+        for (index_id, cfg) in configs {
+            let index_name = ioi.resolve(*index_id);
+            let idx = &self.indices[index_id.to_usize()];
+
+            let mut entries: Vec<_> = idx.iter().into_iter().collect();
+            entries.sort_by_key(|t| t.1);
+
+            for (entry_id, entry_name) in entries {
+                let eidx = entry_id.to_usize();
+
+                // We only check entries with locations of their own.
+                let loc = match idx.locs.get_holey_slot(eidx) {
+                    Some(l) => l,
+                    None => continue,
+                };
+
+                let (input, line) = idx.def_inputs.get_holey_slot(eidx).unwrap();
+                let input = inputs.resolve(input);
+
+                if cfg.require_text && !idx.texts.holey_slot_is_filled(eidx) {
+                    bus_tx.file_error(
+                        input,
+                        line,
+                        format!(
+                            "index entry `{}:{}` has no text, but index {} requires it",
+                            index_name,
+                            entry_name,
+                            self.index_label(*index_id)
+                        ),
+                        Vec::new(),
+                    );
+                    failures.fail(input);
+                    n_failures += 1;
+                }
+
+                if let Some(prefix) = cfg.url_prefix.as_ref() {
+                    let output = outputs.resolve(loc.output);
+
+                    if !output.starts_with(prefix.as_str()) {
+                        bus_tx.file_error(
+                            input,
+                            line,
+                            format!(
+                                "index entry `{}:{}` is defined in output `{}`, but entries of index {} must be in outputs under `{}`",
+                                index_name, entry_name, output, self.index_label(*index_id), prefix
+                            ),
+                            Vec::new(),
+                        );
+                        failures.fail(input);
+                        n_failures += 1;
+                    }
+                }
+
+                if cfg.warn_unreferenced && !referenced.contains(&(*index_id, entry_id)) {
+                    bus_tx.file_warning(
+                        input,
+                        line,
+                        format!(
+                            "index entry `{}:{}` is never referenced",
+                            index_name, entry_name
+                        ),
+                        None,
+                    );
+                }
+            }
+        }
+
+        match n_failures {
+            0 => Ok(()),
+            _ if failures.keep_going() => Ok(()),
+            1 => Err(anyhow!("1 index entry violates its index settings")),
+            n => Err(anyhow!("{} index entries violate their index settings", n)),
+        }
+    }
+
+    /// Get the redirects that should be emitted for aliased entries.
+    ///
+    /// If an alias's target is defined at the top of an output named after it,
+    /// following the pattern `.../TARGET/index.html` used by `\Entry`, we
+    /// can create a redirect at the corresponding path for the alias, so that
+    /// old links into the output tree keep working. Aliases that don't fit
+    /// this pattern, or whose redirect would clobber an actual output, are
+    /// skipped. So are aliases that can't be used as a component of an output
+    /// path (see [`normalize_output_path`]), with a warning at the alias
+    /// declaration. The returned redirects are sorted by path.
+    fn alias_redirects(&self, status: &mut SyncMessageBusSender) -> Vec<AliasRedirect> {
+        let ioi = &self.indices[INDEX_OF_INDICES_INDEX];
+        let inputs = &self.indices[INPUTS_INDEX_INDEX];
+        let outputs = &self.indices[OUTPUTS_INDEX_INDEX];
+        let mut redirects = Vec::new();
+
+        for (index_id, _) in ioi.iter() {
+            if index_id.to_usize() <= FRAGMENTS_INDEX_INDEX {
+                continue;
+            }
+
+            let idx = &self.indices[index_id.to_usize()];
+
+            for (entry_id, alias) in idx.iter() {
+                if !idx.is_alias(entry_id) {
+                    continue;
+                }
+
+                let target = match idx.canonical(entry_id) {
+                    Some(t) => t,
+                    None => continue,
+                };
+
+                let loc = match idx.get_location(target) {
+                    Some(l) => l,
+                    None => continue,
+                };
+
+                let tname = idx.resolve(target);
+                let tout = outputs.resolve(loc.output);
+                let tail = format!("{}/index.html", tname);
+
+                // If the index has a configured URL prefix, we can trust it
+                // for the paths of the aliases.
+                let cfg_prefix = self
+                    .configs
+                    .get(&index_id)
+                    .and_then(|c| c.url_prefix.as_deref());
+
+                let prefix = match (cfg_prefix, tout.strip_suffix(&tail)) {
+                    (Some(p), _) => p,
+                    (None, Some(p)) if p.is_empty() || p.ends_with('/') => p,
+                    _ => continue,
+                };
+
+                // The alias becomes a single component of an output path, so it
+                // must pass the same checks as declared outputs, and must not
+                // be changed by their normalization.
+
+                let path = format!("{}{}/index.html", prefix, alias);
+
+                let problem = match normalize_output_path(&path) {
+                    Ok(p) if p == path && !alias.contains('/') => None,
+                    Ok(_) => Some(anyhow!("the alias must be a single path component")),
+                    Err(e) => Some(e),
+                };
+
+                if let Some(e) = problem {
+                    let (input, line) = idx
+                        .alias_inputs
+                        .get_holey_slot(entry_id.to_usize())
+                        .unwrap();
+
+                    status.file_warning(
+                        inputs.resolve(input),
+                        line,
+                        format!(
+                            "can't create a redirect for alias `{}:{}` at `{}`",
+                            ioi.resolve(index_id),
+                            alias,
+                            path
+                        ),
+                        Some(e),
+                    );
+                    continue;
+                }
+
+                if let Some(id) = outputs.get(&path) {
+                    if self.output_sources.contains_key(&id) {
+                        continue;
+                    }
+                }
+
+                // The URL is relative to the redirect file.
+                let mut url = "../".repeat(path.matches('/').count());
+                url.push_str(&self.location_url(loc));
+
+                redirects.push(AliasRedirect {
+                    index: index_id,
+                    alias: entry_id,
+                    target,
+                    path,
+                    url,
+                });
+            }
+        }
+
+        redirects.sort_by(|a, b| a.path.cmp(&b.path));
+        redirects
+    }
+
+    /// Serialize the alias redirects in CSV format.
+    fn write_redirects<W: Write>(&self, dest: W, redirects: &[AliasRedirect]) -> Result<W> {
+        let mut w = csv::Writer::from_writer(dest);
+
+        w.write_record(["index", "alias", "target", "redirect_output", "target_url"])?;
+
+        for r in redirects {
+            let idx = &self.indices[r.index.to_usize()];
+
+            w.write_record([
+                self.indices[INDEX_OF_INDICES_INDEX].resolve(r.index),
+                idx.resolve(r.alias),
+                idx.resolve(r.target),
+                &r.path,
+                &r.url,
+            ])?;
+        }
+
+        Ok(w.into_inner().map_err(|e| e.into_error())?)
+    }
+
+    /// Find entries with names similar to that of an unresolved reference.
+    ///
+    /// We look for entries satisfying `is_defined` whose names are close to
+    /// `name` in terms of edit distance, first in the index being referenced
+    /// and then in the other user indices. The return value is a list of
+    /// human-readable suggestions, which may be empty.
+    fn suggest_entries<F>(&self, index: IndexId, name: &str, is_defined: F) -> Vec<String>
+    where
+        F: Fn(&Index, EntryId) -> bool,
+    {
+        const MAX_SUGGESTIONS: usize = 3;
+
+        // Allow roughly one typo for every three characters, so that short
+        // names don't match everything under the sun.
+        let max_dist = std::cmp::max(1, name.chars().count() / 3);
+
+        let ioi = &self.indices[INDEX_OF_INDICES_INDEX];
+        let mut same = Vec::new();
+        let mut other = Vec::new();
+
+        for (index_id, index_name) in ioi.iter() {
+            // Only user indices are interesting here.
+            if index_id.to_usize() <= FRAGMENTS_INDEX_INDEX {
+                continue;
+            }
+
+            let idx = &self.indices[index_id.to_usize()];
+
+            for (entry_id, entry_name) in idx.iter() {
+                if !is_defined(idx, entry_id) {
+                    continue;
+                }
+
+                let dist = edit_distance(name, entry_name);
+
+                if dist > max_dist {
+                    continue;
+                }
+
+                if index_id == index {
+                    same.push((dist, index_name, entry_name));
+                } else {
+                    other.push((dist, index_name, entry_name));
+                }
+            }
+        }
+
+        same.sort();
+        other.sort();
+
+        let mut suggestions = Vec::new();
+
+        for (_, i, e) in same.iter().take(MAX_SUGGESTIONS) {
+            suggestions.push(format!("did you mean `{}:{}`?", i, e));
+        }
+
+        for (_, i, e) in other.iter().take(MAX_SUGGESTIONS) {
+            suggestions.push(format!(
+                "a similar entry exists in another index: `{}:{}`",
+                i, e
+            ));
+        }
+
+        suggestions
+    }
+
+    /// Get a user-friendly(ish) summary of the indexing data.
+    pub fn index_summary(&self) -> String {
+        let n_indices = self.indices.len();
+
+        let mut n_entries = 0;
+
+        for idx in &self.indices {
+            n_entries += idx.entries.len();
+        }
+
+        format!("{n_entries} entries in {n_indices} indices")
+    }
+
+    /// Get the TeX code defining the resolved index references used by the
+    /// specified input.
+    ///
+    /// The output is sorted by index and entry name, so that it only changes
+    /// if the resolved references actually change. Pass 2 depends on a digest
+    /// of this text, so this is important for avoiding needless rebuilds.
+    pub fn get_resolved_reference_tex(&self, input: InputId) -> String {
+        // Because we have validated cross-references, we can unwrap everything
+        // here without worrying about missing values.
+        let mut refs: Vec<_> = self.refs.lookup(input.to_usize()).unwrap().iter().collect();
+        refs.sort_by_key(|r| {
+            (
+                self.indices[INDEX_OF_INDICES_INDEX].resolve(r.index),
+                self.indices[r.index.to_usize()].resolve(r.entry),
+            )
+        });
+
+        let mut tex = String::new();
+
+        for entry in refs {
+            let iname = self.indices[INDEX_OF_INDICES_INDEX].resolve(entry.index);
+            let iindex = entry.index.to_usize();
+            let ename = self.indices[iindex].resolve(entry.entry);
+            let f = entry.flags;
+
+            if (f & IndexRefFlag::NeedsLoc as u8) != 0 {
+                let url = self.entry_url(entry.index, entry.entry).unwrap();
+
+                write!(
+                    tex,
+                    r"\expandafter\def\csname pedia resolve**{}**{}**loc\endcsname{{",
+                    iname, ename
+                )
+                .unwrap();
+                encode_tex_url_to_string(url, &mut tex);
+                writeln!(tex, r"}}").unwrap();
+
+                if self.indices[iindex].external {
+                    writeln!(
+                        tex,
+                        r"\expandafter\def\csname pedia resolve**{}**{}**external\endcsname{{}}",
+                        iname, ename
+                    )
+                    .unwrap();
+                }
+            }
+
+            if (f & IndexRefFlag::NeedsText as u8) != 0 {
+                let text = self.indices[iindex].get_text(entry.entry).unwrap();
+                writeln!(
                     tex,
                     r"\expandafter\def\csname pedia resolve**{}**{}**text tex\endcsname{{{}}}",
                     iname, ename, text.tex
@@ -493,11 +1736,108 @@ impl IndexCollection {
                 encode_tex_to_string(text.plain, &mut tex);
                 writeln!(tex, r"}}",).unwrap();
             }
+
+            if (f & IndexRefFlag::NeedsBacklinks as u8) != 0 {
+                self.write_backlinks_tex(iname, ename, (entry.index, entry.entry), &mut tex);
+            }
         }
 
         tex
     }
 
+    /// Write the TeX code defining the backlinks of an entry.
+    ///
+    /// References from the output in which the entry is defined are omitted,
+    /// since links from a page to itself aren't interesting.
+    fn write_backlinks_tex(
+        &self,
+        iname: &str,
+        ename: &str,
+        target: (IndexId, EntryId),
+        tex: &mut String,
+    ) {
+        let idx = &self.indices[target.0.to_usize()];
+        let own_output = idx.get_location(target.1).map(|l| l.output);
+        let target = match idx.canonical(target.1) {
+            Some(e) => (target.0, e),
+            None => target,
+        };
+
+        writeln!(
+            tex,
+            r"\expandafter\def\csname pedia resolve**{}**{}**backlinks\endcsname{{%",
+            iname, ename
+        )
+        .unwrap();
+
+        for loc in self.backlinks.get(&target).into_iter().flatten() {
+            if Some(loc.output) == own_output {
+                continue;
+            }
+
+            write!(tex, r"\pediaBacklink{{").unwrap();
+            encode_tex_url_to_string(self.location_url(*loc), tex);
+            write!(tex, r"}}{{").unwrap();
+
+            match self.output_titles.get(&loc.output) {
+                Some((i, e)) => {
+                    let text = self.indices[i.to_usize()].get_text(*e).unwrap();
+                    tex.push_str(&text.tex);
+                }
+
+                None => {
+                    let o = self.indices[OUTPUTS_INDEX_INDEX].resolve(loc.output);
+                    encode_tex_to_string(o, tex);
+                }
+            }
+
+            writeln!(tex, r"}}%").unwrap();
+        }
+
+        writeln!(tex, r"}}").unwrap();
+    }
+
+    /// Serialize the backlinks table in CSV format.
+    ///
+    /// We sort by index and entry name to hopefully keep the outputs
+    /// reproducible.
+    fn write_backlinks<W: Write>(&self, dest: W) -> Result<W> {
+        let ioi = &self.indices[INDEX_OF_INDICES_INDEX];
+        let outputs = &self.indices[OUTPUTS_INDEX_INDEX];
+        let fragments = &self.indices[FRAGMENTS_INDEX_INDEX];
+
+        let mut all: Vec<_> = self
+            .backlinks
+            .iter()
+            .map(|((i, e), locs)| {
+                (
+                    ioi.resolve(*i),
+                    self.indices[i.to_usize()].resolve(*e),
+                    locs,
+                )
+            })
+            .collect();
+        all.sort_by_key(|t| (t.0, t.1));
+
+        let mut w = csv::Writer::from_writer(dest);
+
+        w.write_record(["index", "entry", "ref_output", "ref_fragment"])?;
+
+        for (iname, ename, locs) in all.drain(..) {
+            for loc in locs {
+                let rec = &[
+                    iname,
+                    ename,
+                    outputs.resolve(loc.output),
+                    fragments.resolve(loc.fragment),
+                ];
+                w.write_record(rec)?;
+            }
+        }
+
+        Ok(w.into_inner().map_err(|e| e.into_error())?)
+    }
+
     pub fn load_user_indices(&mut self) -> Result<()> {
         // Hardcoding that we're running from the root directory!
         let entries = atry!(
@@ -541,12 +1881,104 @@ impl IndexCollection {
 
             // Finally we can actually deal with this item
 
-            atry!(
+            let index = atry!(
                 self.declare_index(&rec.index.name);
                 ["failed to declare the index defined in file `{}`", path.display()]
             );
+
+            let relpath = format!("idx/{}", entry.file_name().to_string_lossy());
+            let config = atry!(
+                IndexConfig::new(relpath, &rec);
+                ["invalid settings in index definition file `{}`", path.display()]
+            );
+            self.configs.insert(index, config);
+
+            if let Some(inv) = rec.inventory {
+                atry!(
+                    self.load_inventory(index, &inv);
+                    ["failed to load the inventory of the index defined in file `{}`", path.display()]
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Load an external index from an inventory file.
+    ///
+    /// The inventory is a JSON or CSV file listing entries with their URLs and
+    /// (optionally) plain-text representations. If the URLs are relative, they
+    /// are resolved relative to the inventory's base URL. After loading, the
+    /// index cannot be modified.
+    fn load_inventory(&mut self, index: IndexId, inv: &syntax::Inventory) -> Result<()> {
+        let mut path = self.root.clone();
+        path.push(&inv.path);
+
+        let file = atry!(
+            File::open(&path);
+            ["failed to open inventory file `{}`", path.display()]
+        );
+
+        let records: Vec<syntax::InventoryEntry> = if inv.path.ends_with(".json") {
+            atry!(
+                serde_json::from_reader(BufReader::new(file));
+                ["failed to parse inventory file `{}` as JSON", path.display()]
+            )
+        } else if inv.path.ends_with(".csv") {
+            let mut r = csv::Reader::from_reader(file);
+            let records: std::result::Result<Vec<_>, _> = r.deserialize().collect();
+            atry!(
+                records;
+                ["failed to parse inventory file `{}` as CSV", path.display()]
+            )
+        } else {
+            bail!(
+                "inventory file `{}` must have a `.json` or `.csv` extension",
+                path.display()
+            );
+        };
+
+        let idx = &mut self.indices[index.to_usize()];
+
+        for rec in records {
+            let url = match (&inv.base_url, is_absolute_url(&rec.url)) {
+                (Some(base), false) => {
+                    let sep = if base.ends_with('/') { "" } else { "/" };
+                    format!("{}{}{}", base, sep, rec.url.trim_start_matches('/'))
+                }
+                _ => rec.url,
+            };
+
+            ensure!(
+                is_absolute_url(&url),
+                "the URL `{}` of entry `{}` in inventory file `{}` is not absolute, and no `base_url` is set",
+                url,
+                rec.entry,
+                path.display()
+            );
+
+            let entry = idx.reference(&rec.entry);
+            let eidx = entry.to_usize();
+
+            ensure!(
+                idx.urls.ensure_holey_slot_available(eidx).is_ok(),
+                "entry `{}` appears more than once in inventory file `{}`",
+                rec.entry,
+                path.display()
+            );
+
+            idx.urls[eidx] = Some(url);
+
+            if let Some(plain) = rec.text {
+                let mut tex = String::new();
+                encode_tex_to_string(&plain, &mut tex);
+                // This can't fail, because we just checked for duplicates.
+                let _ = idx.define_text(&rec.entry, EntryText { tex, plain });
+            }
         }
 
+        idx.external = true;
+        self.inventories.insert(index, inv.path.clone());
         Ok(())
     }
 
@@ -580,6 +2012,23 @@ impl IndexCollection {
         RuntimeEntityIdent::OtherFile(id)
     }
 
+    /// Create an entity identifier for an environment variable.
+    ///
+    /// This returns a value of [`RuntimeEntityIdent::EnvironmentVariable`].
+    pub fn make_environment_variable_ident(&mut self, name: impl AsRef<str>) -> RuntimeEntityIdent {
+        let id = self.env_var_names.get_or_intern(name);
+        RuntimeEntityIdent::EnvironmentVariable(id)
+    }
+
+    /// Get the name of the environment variable associated with an entity
+    /// identifier, if it has one.
+    pub(crate) fn name_for_environment_variable(&self, rei: RuntimeEntityIdent) -> Option<&str> {
+        match rei {
+            RuntimeEntityIdent::EnvironmentVariable(s) => self.env_var_names.resolve(s),
+            _ => None,
+        }
+    }
+
     /// Convert a [`RuntimeEntityIdent`] to a [`PersistEntityIdent`].
     pub fn persist_ident(&self, rei: RuntimeEntityIdent) -> PersistEntityIdent {
         match rei {
@@ -597,7 +2046,15 @@ impl IndexCollection {
                 let p = self.indices[OTHER_PATHS_INDEX_INDEX].resolve(s);
                 PersistEntityIdent::OtherFile(p.to_owned())
             }
-        }
+
+            RuntimeEntityIdent::EnvironmentVariable(s) => {
+                let n = self.env_var_names.resolve(s).unwrap();
+                PersistEntityIdent::EnvironmentVariable(n.to_owned())
+            }
+
+            RuntimeEntityIdent::TexBundle => PersistEntityIdent::TexBundle,
+            RuntimeEntityIdent::ProgramVersion => PersistEntityIdent::ProgramVersion,
+        }
     }
 
     /// Get a filesystem path associated with a [`PersistEntityIdent`], if one
@@ -622,10 +2079,13 @@ impl IndexCollection {
                 p.push(self.indices[OTHER_PATHS_INDEX_INDEX].resolve(s));
                 p
             }
+
+            _ => bail!(
+                "entity `{}` is not associated with a path",
+                self.persist_ident(rei)
+            ),
         };
 
-        /// One day, we may have idents that don't have associated paths, but
-        /// right now, they all do.
         Ok(p)
     }
 
@@ -666,6 +2126,9 @@ impl IndexCollection {
             PersistEntityIdent::TexSourceFile(p) => self.make_tex_source_ident(p),
             PersistEntityIdent::OutputFile(p) => self.make_output_file_ident(p),
             PersistEntityIdent::OtherFile(p) => self.make_other_file_ident(p),
+            PersistEntityIdent::EnvironmentVariable(n) => self.make_environment_variable_ident(n),
+            PersistEntityIdent::TexBundle => RuntimeEntityIdent::TexBundle,
+            PersistEntityIdent::ProgramVersion => RuntimeEntityIdent::ProgramVersion,
         }
     }
 }
@@ -676,6 +2139,10 @@ pub struct IndexRef {
     pub index: IndexId,
     pub entry: EntryId,
     pub flags: IndexRefFlags,
+
+    /// The input line of the first reference to this entry, or zero if
+    /// unknown.
+    pub line: u32,
 }
 
 /// A location in the output, specified by an ouput path name and a URL fragment
@@ -692,7 +2159,7 @@ impl OutputLocation {
     }
 }
 
-#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Hash, Eq, PartialEq, Serialize)]
 pub struct EntryText {
     /// Some text in TeX markup, suitable for direct insertion into TeX source
     /// code. E.g., `"\\TeX \\& \\LaTeX"`.
@@ -703,47 +2170,215 @@ pub struct EntryText {
     pub plain: String,
 }
 
+/// A redirect that should be emitted for an aliased index entry.
+#[derive(Clone, Debug)]
+struct AliasRedirect {
+    index: IndexId,
+    alias: EntryId,
+    target: EntryId,
+
+    /// The path of the redirect file, relative to the output tree.
+    path: String,
+
+    /// The URL of the target, relative to the redirect file.
+    url: String,
+}
+
 pub type IndexRefFlags = u8;
 
 #[repr(u8)]
+#[allow(clippy::enum_variant_names)]
 pub enum IndexRefFlag {
     NeedsLoc = 1 << 0,
     NeedsText = 1 << 1,
+    NeedsBacklinks = 1 << 2,
 }
 
-// The index construction phase of the build
+/// Format an input path and line number as `path:line`, or just `path` if the
+/// line number is zero, meaning unknown.
+fn located(input: &str, line: u32) -> String {
+    if line > 0 {
+        format!("{}:{}", input, line)
+    } else {
+        input.to_owned()
+    }
+}
 
-/// Set up the indices for pass 2 and validate them.
+/// Normalize a declared output path, checking that it is safe to use as a
+/// location inside the build directory, and as part of a URL.
 ///
-/// We generate a collection of CSV files capturing the state of the indices.
-/// However, these files are never actually read during the primary build
-/// process! Even if they're fully up-to-date, we need to reread the Pass 1
-/// metadata files to properly build up our internal data structure of
-/// cross-references by input. But, creating these files gives us a way to
-/// properly trigger Pass 2 rebuilds for the subset of inputs that need them,
-/// and they could potentially be useful for manual inspection.
-pub fn construct_indices(
+/// The path must be relative, and may not have `..` components or end with a
+/// slash. Harmless `.` components and repeated slashes are removed. Only the
+/// characters that can appear in URL paths without escaping are allowed: ASCII
+/// letters and digits, and `-._~!$&'()*+,;=@`. Colons are also allowed in URL
+/// paths, but not here, since they have special meaning in Windows paths.
+pub fn normalize_output_path(path: &str) -> Result<String> {
+    ensure!(!path.is_empty(), "the path is empty");
+    ensure!(!path.starts_with('/'), "the path must be relative");
+    ensure!(
+        !path.ends_with('/'),
+        "the path must name a file, not a directory"
+    );
+
+    if let Some(c) = path
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || "-._~!$&'()*+,;=@/".contains(*c)))
+    {
+        bail!("the character {:?} is not allowed", c);
+    }
+
+    let mut components = Vec::new();
+
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => bail!("the path has a `..` component"),
+            _ => components.push(component),
+        }
+    }
+
+    ensure!(!components.is_empty(), "the path does not name a file");
+    Ok(components.join("/"))
+}
+
+/// Check whether a URL is absolute, in the sense that it can be used as-is
+/// from any page: it either starts with a scheme (`https:`, `mailto:`, ...),
+/// or is a protocol-relative `//host/path` reference.
+fn is_absolute_url(url: &str) -> bool {
+    if url.starts_with("//") {
+        return true;
+    }
+
+    let scheme = match url.split_once(':') {
+        Some((scheme, _)) => scheme,
+        None => return false,
+    };
+
+    let mut chars = scheme.chars();
+
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || "+.-".contains(c))
+}
+
+/// Compute the Levenshtein edit distance between two strings, counting
+/// characters rather than bytes.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diag = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let subst = diag + if ca == *cb { 0 } else { 1 };
+            diag = row[j + 1];
+            row[j + 1] = std::cmp::min(subst, std::cmp::min(row[j], row[j + 1]) + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
+// The index construction phase of the build
+
+/// Compute the identifier of the cross-indexing operation.
+pub fn cross_index_operation_id(
+    metadata_ids: &[RuntimeEntityIdent],
     indices: &mut IndexCollection,
+) -> DigestData {
+    cross_index_opid_and_inputs(metadata_ids, indices).0
+}
+
+/// Compute the identifier of the cross-indexing operation, along with the
+/// identifiers of its inputs other than the metadata files.
+fn cross_index_opid_and_inputs(
     metadata_ids: &[RuntimeEntityIdent],
-    cache: &mut Cache,
-    status: &mut SyncMessageBusSender,
-) -> Result<()> {
+    indices: &mut IndexCollection,
+) -> (DigestData, Vec<RuntimeEntityIdent>) {
     // Set up the information about the operation. The operation identifier
     // must include *all* inputs since if, say, we add a new one, we'll need
     // to rerun the op, and a simple check that all of the old inputs are
     // unchanged won't catch that.
 
     let mut dc = DigestComputer::default();
-    dc.update("cross_index_v2");
+    dc.update("cross_index_v7");
 
     for input in metadata_ids {
         input.update_digest(&mut dc, indices);
     }
 
-    let opid = dc.finalize();
+    // The external inventories feed into the indices too.
+
+    let inventory_paths: Vec<String> = indices
+        .inventory_paths()
+        .into_iter()
+        .map(|p| p.to_owned())
+        .collect();
 
-    let needs_reemit = atry!(
-        cache.operation_needs_rerun(&opid, indices, status);
+    let inventory_ids: Vec<_> = inventory_paths
+        .into_iter()
+        .map(|p| RuntimeEntityIdent::new_other_file(p, indices))
+        .collect();
+
+    for input in &inventory_ids {
+        input.update_digest(&mut dc, indices);
+    }
+
+    // So do the settings in the index definition files.
+
+    let config_paths: Vec<String> = indices
+        .config_paths()
+        .into_iter()
+        .map(|p| p.to_owned())
+        .collect();
+
+    let mut config_ids: Vec<_> = config_paths
+        .into_iter()
+        .map(|p| RuntimeEntityIdent::new_other_file(p, indices))
+        .collect();
+
+    for input in &config_ids {
+        input.update_digest(&mut dc, indices);
+    }
+
+    let mut extra_inputs = inventory_ids;
+    extra_inputs.append(&mut config_ids);
+    (dc.finalize(), extra_inputs)
+}
+
+/// Set up the indices for pass 2 and validate them.
+///
+/// We generate a collection of CSV files capturing the state of the indices.
+/// However, these files are never actually read during the primary build
+/// process! Even if they're fully up-to-date, we need to rebuild our internal
+/// data structure of cross-references by input, either from the snapshot of
+/// the indices saved by the previous build, or from the Pass 1 metadata. But,
+/// creating these files gives us a way to trigger downstream operations (like
+/// the entrypoint generation) that depend on whole indices, and they could
+/// potentially be useful for manual inspection. Pass 2 operations depend
+/// instead on the resolved references of their specific inputs.
+///
+/// The table of backlinks (which outputs refer to which entries) is written
+/// out alongside the indices, as `cache/idx/backlinks.csv`. So are the
+/// redirects that should be created for entry aliases, in
+/// `cache/idx/redirects.csv`; see [`crate::redirects`]. Finally, the list of
+/// tags goes into `cache/idx/tags.csv`, and the outputs having each tag into
+/// `cache/tags/TAG.csv`; see [`crate::tag_pages`].
+///
+/// Inputs that fail validation, such as those with unresolved references, are
+/// recorded in *failures*; see [`IndexCollection::validate_references`].
+pub fn construct_indices(
+    indices: &mut IndexCollection,
+    metadata_ids: &[RuntimeEntityIdent],
+    cache: &mut Cache,
+    failures: &FailureLog,
+    status: &mut SyncMessageBusSender,
+) -> Result<()> {
+    let (opid, extra_inputs) = cross_index_opid_and_inputs(metadata_ids, indices);
+
+    let rerun_reason = atry!(
+        cache.operation_needs_rerun(&opid, "the cross-indexing", indices, status);
         ["failed to probe cache for cross-indexing operation"]
     );
 
@@ -764,34 +2399,172 @@ pub fn construct_indices(
         .map(|p| RuntimeEntityIdent::new_other_file(p, indices))
         .collect();
 
+    let backlinks_file = RuntimeEntityIdent::new_other_file("cache/idx/backlinks.csv", indices);
+    let redirects_file = RuntimeEntityIdent::new_other_file("cache/idx/redirects.csv", indices);
+
     // Regardless of whether we need to re-emit the files, we need to
     // reconstruct the information that maps input files to their index
     // references for pass 2.
 
     let mut ocd = OpCacheData::new(opid);
 
+    for input in &extra_inputs {
+        ocd.add_input(*input);
+    }
+
+    let mut digests = Vec::with_capacity(metadata_ids.len());
+
     for input in metadata_ids {
         ocd.add_input(*input);
+        digests.push(cache.require_entity(*input, indices)?.value_digest);
+    }
 
-        let (input_id, index_refs) = atry!(
-            load_metadata(*input, indices, cache, status);
-            ["failed to load metadata for `{:?}`", input]
-        );
+    // If none of the inputs to the indices have changed since the last build,
+    // we can restore the indices from the snapshot that it saved. The key of
+    // the snapshot covers the contents of the inputs as well as their
+    // identities. See [`crate::metadata_snapshot`].
 
-        // This function only fails if the references for the given input have
-        // already been logged, which should never happen to us.
-        indices.log_references(input_id, index_refs).unwrap();
+    let mut dc = DigestComputer::default();
+    dc.update(opid);
+
+    for digest in &digests {
+        dc.update(digest);
+    }
+
+    for input in &extra_inputs {
+        dc.update(cache.require_entity(*input, indices)?.value_digest);
+    }
+
+    let key = dc.finalize();
+
+    // The snapshot is restored into a scratch copy of the indices, so that
+    // if it turns out to be unusable, we can fall back to loading the
+    // metadata into the pristine indices.
+
+    let restored = match metadata_snapshot::read_index_snapshot(&indices.root, &key) {
+        Ok(Some(snapshot)) => {
+            let mut scratch = indices.clone();
+
+            match scratch.restore_snapshot(snapshot) {
+                Ok(warnings) => {
+                    *indices = scratch;
+                    Some(warnings)
+                }
+
+                Err(e) => {
+                    tt_warning!(status, "ignoring the saved index snapshot"; e);
+                    None
+                }
+            }
+        }
+
+        Ok(None) => None,
+
+        Err(e) => {
+            tt_warning!(status, "ignoring the saved index snapshot"; e);
+            None
+        }
+    };
+
+    let warnings = match restored {
+        Some(w) => w,
+
+        None => {
+            let warnings = load_metadata(indices, metadata_ids, &digests, status)?;
+            indices.fold_case();
+            indices.finalize_backlinks();
+
+            let snapshot = indices.snapshot(warnings);
+
+            if let Err(e) = metadata_snapshot::write_index_snapshot(&indices.root, &key, &snapshot)
+            {
+                tt_warning!(status, "failed to save the index snapshot"; e);
+            }
+
+            snapshot.warnings
+        }
+    };
+
+    for alert in warnings {
+        status.post(Message::Warning(alert));
     }
 
     atry!(
-        indices.validate_references(status);
+        indices.validate_outputs(failures, status);
+        ["failed to validate output declarations"]
+    );
+
+    atry!(
+        indices.validate_definitions(failures, status);
+        ["failed to validate index entry definitions"]
+    );
+
+    atry!(
+        indices.validate_aliases(failures, status);
+        ["failed to validate index aliases"]
+    );
+
+    atry!(
+        indices.validate_references(failures, status);
         ["failed to validate cross-references"]
     );
 
+    atry!(
+        indices.validate_settings(failures, status);
+        ["failed to validate index entries against their index settings"]
+    );
+
+    // The redirects for aliases are outputs, so they need to be registered
+    // before we write out the outputs index, so that the entrypoint picks
+    // them up.
+
+    let redirects = indices.alias_redirects(status);
+
+    for r in &redirects {
+        indices.make_output_file_ident(&r.path);
+    }
+
+    // Same for the tag listing pages. We can only register the files for the
+    // tags now that we've loaded the metadata.
+
+    let tags_file = RuntimeEntityIdent::new_other_file("cache/idx/tags.csv", indices);
+
+    let tag_names: Vec<String> = indices
+        .tag_names()
+        .into_iter()
+        .map(|t| t.to_owned())
+        .collect();
+    let mut tag_files = Vec::new();
+
+    if !tag_names.is_empty() {
+        indices.make_output_file_ident(crate::tag_pages::TAG_INDEX_OUTPUT);
+    }
+
+    for tag in &tag_names {
+        indices.make_output_file_ident(crate::tag_pages::tag_output(tag));
+        tag_files.push(RuntimeEntityIdent::new_other_file(
+            format!("cache/tags/{}.csv", tag),
+            indices,
+        ));
+    }
+
+    // And the A–Z listing pages of the user indices, which are driven by the
+    // index files that we're about to write.
+
+    let listing_names: Vec<String> = indices
+        .listings()
+        .into_iter()
+        .map(|(name, _)| name.to_owned())
+        .collect();
+
+    for name in &listing_names {
+        indices.make_output_file_ident(crate::index_pages::listing_output(name));
+    }
+
     // Yay, indices are good. If we believe they've changed, write new files to
     // disk.
 
-    if needs_reemit {
+    if rerun_reason.is_some() {
         let ioi_index = &indices.indices[INDEX_OF_INDICES_INDEX];
         let outputs_index = &indices.indices[OUTPUTS_INDEX_INDEX];
         let fragments_index = &indices.indices[FRAGMENTS_INDEX_INDEX];
@@ -815,6 +2588,28 @@ pub fn construct_indices(
             ocd.add_output_with_value(index_files[index_id], entity.value_digest, size);
         }
 
+        let stream = OpOutputStream::new(backlinks_file, indices)?;
+        let mut stream = indices.write_backlinks(stream)?;
+        let (entity, size) = stream.close()?;
+        ocd.add_output_with_value(backlinks_file, entity.value_digest, size);
+
+        let stream = OpOutputStream::new(redirects_file, indices)?;
+        let mut stream = indices.write_redirects(stream, &redirects)?;
+        let (entity, size) = stream.close()?;
+        ocd.add_output_with_value(redirects_file, entity.value_digest, size);
+
+        let stream = OpOutputStream::new(tags_file, indices)?;
+        let mut stream = indices.write_tags(stream)?;
+        let (entity, size) = stream.close()?;
+        ocd.add_output_with_value(tags_file, entity.value_digest, size);
+
+        for (tag, tag_file) in tag_names.iter().zip(tag_files) {
+            let stream = OpOutputStream::new(tag_file, indices)?;
+            let mut stream = indices.write_tag_members(stream, tag)?;
+            let (entity, size) = stream.close()?;
+            ocd.add_output_with_value(tag_file, entity.value_digest, size);
+        }
+
         // Cache it and we're done!
 
         atry!(
@@ -826,54 +2621,148 @@ pub fn construct_indices(
     Ok(())
 }
 
+/// Load the metadata of all of the inputs into the indices, returning the
+/// warnings that arose.
+///
+/// The parsed metadata are saved between builds, so we only need to parse the
+/// metadata files that have changed. See [`crate::metadata_snapshot`].
 fn load_metadata(
-    input: RuntimeEntityIdent,
     indices: &mut IndexCollection,
-    cache: &mut Cache,
+    metadata_ids: &[RuntimeEntityIdent],
+    digests: &[DigestData],
     status: &mut SyncMessageBusSender,
-) -> Result<(InputId, impl IntoIterator<Item = IndexRef>)> {
-    let outputs_id = indices.get_index("outputs").unwrap();
-    let mut cur_output = None;
-    let mut index_refs = HashMap::new();
+) -> Result<Vec<AlertMessage>> {
+    let snapshot_data = match metadata_snapshot::read_snapshot(&indices.root) {
+        Ok(d) => d,
+        Err(e) => {
+            tt_warning!(status, "ignoring the saved metadata snapshot"; e);
+            Vec::new()
+        }
+    };
 
-    let meta_path = indices.path_for_runtime_ident(input).unwrap();
+    let saved = match metadata_snapshot::decode_snapshot(&snapshot_data) {
+        Ok(s) => s,
+        Err(e) => {
+            tt_warning!(status, "ignoring the saved metadata snapshot"; e);
+            HashMap::new()
+        }
+    };
 
-    let meta_file = atry!(
-        File::open(&meta_path);
-        ["failed to open input `{}`", meta_path.display()]
-    );
+    let mut texts = Vec::new();
 
-    let mut meta_buf = BufReader::new(meta_file);
+    for (input, digest) in metadata_ids.iter().zip(digests) {
+        if !saved.contains_key(digest) {
+            let meta_path = indices.path_for_runtime_ident(*input).unwrap();
+            let text = atry!(
+                std::fs::read_to_string(&meta_path);
+                ["failed to read input `{}`", meta_path.display()]
+            );
+            texts.push((*digest, meta_path, text));
+        }
+    }
 
-    let mut context = String::new();
-    atry!(
-        meta_buf.read_line(&mut context);
-        ["failed to read input `{}`", meta_path.display()]
-    );
+    let mut parsed = HashMap::new();
 
-    // Note: read_line() includes the trailing newline!
-    let input_id = if let Some(input_path) = context.strip_prefix("% input ") {
-        // This can only fail if the index name is undefined, which is impossible here.
-        indices
-            .reference(INPUTS_INDEX_NAME, input_path.trim_end())
-            .unwrap()
-    } else {
-        bail!(
-            "unexpected first line of metadata file `{}`",
-            meta_path.display()
+    for (digest, meta_path, text) in &texts {
+        let meta = atry!(
+            InputMetadata::parse(text);
+            ["failed to parse metadata file `{}`", meta_path.display()]
         );
-    };
+        parsed.insert(*digest, meta);
+    }
 
-    for line in meta_buf.lines() {
-        let line = atry!(
-            line;
-            ["failed to read input `{}`", meta_path.display()]
+    let mut warnings = Vec::new();
+
+    for (input, digest) in metadata_ids.iter().zip(digests) {
+        // One of these will always succeed by construction.
+        let meta = parsed.get(digest).or_else(|| saved.get(digest)).unwrap();
+
+        let (input_id, index_refs) = atry!(
+            apply_metadata(meta, indices, &mut warnings);
+            ["failed to load metadata for `{:?}`", input]
         );
 
-        match Metadatum::parse(&line)? {
+        // This function only fails if the references for the given input have
+        // already been logged, which should never happen to us.
+        indices.log_references(input_id, index_refs).unwrap();
+    }
+
+    // Save a new snapshot if anything has changed. Snapshot entries for
+    // metadata files that are no longer used are dropped.
+
+    if !parsed.is_empty() || saved.len() != digests.len() {
+        let inputs = digests
+            .iter()
+            .map(|d| (*d, parsed.get(d).or_else(|| saved.get(d)).unwrap()))
+            .collect();
+
+        if let Err(e) = metadata_snapshot::write_snapshot(&indices.root, inputs) {
+            tt_warning!(status, "failed to save the metadata snapshot"; e);
+        }
+    }
+
+    Ok(warnings)
+}
+
+/// Record a warning about a metadata record, to be issued once the metadata of
+/// all of the inputs have been loaded. We hold on to the warnings so that they
+/// can be saved in the snapshot of the indices, and issued again when it's
+/// restored.
+fn metadata_warning(
+    warnings: &mut Vec<AlertMessage>,
+    input: &str,
+    line: u32,
+    message: impl ToString,
+    err: Option<Error>,
+) {
+    let mut alert = AlertMessage::new(Some(input), message, err);
+    alert.line = (line > 0).then_some(line);
+    warnings.push(alert);
+}
+
+/// Apply the parsed metadata of one input to the indices.
+fn apply_metadata(
+    meta: &InputMetadata,
+    indices: &mut IndexCollection,
+    warnings: &mut Vec<AlertMessage>,
+) -> Result<(InputId, impl IntoIterator<Item = IndexRef>)> {
+    let outputs_id = indices.get_index("outputs").unwrap();
+    let mut cur_output = None;
+    let mut cur_anchor = None;
+    let mut in_invalid_output = false;
+    let mut index_refs = HashMap::new();
+
+    // This can only fail if the index name is undefined, which is impossible here.
+    let input_path = meta.input;
+    let input_id = indices.reference(INPUTS_INDEX_NAME, input_path).unwrap();
+
+    for rec in &meta.records {
+        let lineno = rec.line;
+
+        match &rec.datum {
             Metadatum::Output(path) => {
-                // TODO: make sure there are no redundant outputs
-                cur_output = Some(indices.reference_by_id(outputs_id, path));
+                // Output paths come straight from the TeX source, and will be
+                // joined onto the build directory, so they must be checked.
+                // Invalid paths are reported later; the records that follow
+                // them are skipped, since they have no output to go into.
+                let path = match normalize_output_path(path) {
+                    Ok(p) => p,
+                    Err(e) => {
+                        indices.log_invalid_output(input_id, lineno, path, e);
+                        cur_output = None;
+                        cur_anchor = None;
+                        in_invalid_output = true;
+                        continue;
+                    }
+                };
+
+                in_invalid_output = false;
+
+                // Conflicting declarations are reported later.
+                let o = indices.reference_by_id(outputs_id, &path);
+                indices.log_output_source(o, input_id, lineno);
+                cur_output = Some(o);
+                cur_anchor = Some(indices.make_location_by_id(o, ""));
             }
 
             Metadatum::IndexDef {
@@ -882,23 +2771,40 @@ fn load_metadata(
                 fragment,
             } => {
                 if let Err(e) = indices.reference(index, entry) {
-                    tt_warning!(status, "couldn't define entry `{}` in index `{}`", entry, index; e);
+                    metadata_warning(
+                        warnings,
+                        input_path,
+                        lineno,
+                        format!("couldn't define entry `{}` in index `{}`", entry, index),
+                        Some(e),
+                    );
                     continue;
                 }
 
                 let co = match cur_output.as_ref() {
                     Some(o) => *o,
+                    None if in_invalid_output => continue,
                     None => {
-                        tt_warning!(status, "attempt to define entry `{}` in index `{}` before an output has been specified", entry, index);
+                        metadata_warning(warnings, input_path, lineno, format!("attempt to define entry `{}` in index `{}` before an output has been specified", entry, index), None);
                         continue;
                     }
                 };
 
                 let loc = indices.make_location_by_id(co, fragment);
 
-                if let Err(e) = indices.define_loc(index, entry, loc) {
-                    // The error here will contain the contextual information.
-                    tt_warning!(status, "couldn't define an index entry"; e);
+                // References that follow this definition will be attributed
+                // to it when we compute backlinks.
+                cur_anchor = Some(loc);
+
+                // Duplicate definitions are reported later.
+                if let Err(e) = indices.define_loc(index, entry, loc, input_id, lineno) {
+                    metadata_warning(
+                        warnings,
+                        input_path,
+                        lineno,
+                        "couldn't define an index entry",
+                        Some(e),
+                    );
                 }
             }
 
@@ -911,12 +2817,29 @@ fn load_metadata(
                     Ok(ie) => ie,
 
                     Err(e) => {
-                        tt_warning!(status, "couldn't reference entry `{}` in index `{}`", entry, index; e);
+                        metadata_warning(
+                            warnings,
+                            input_path,
+                            lineno,
+                            format!("couldn't reference entry `{}` in index `{}`", entry, index),
+                            Some(e),
+                        );
                         continue;
                     }
                 };
 
-                let cur_flags = index_refs.entry(ie).or_default();
+                // Only references that actually produce links or text
+                // count as backlinks.
+                let linky = IndexRefFlag::NeedsLoc as u8 | IndexRefFlag::NeedsText as u8;
+
+                if let Some(anchor) = cur_anchor {
+                    if (flags & linky) != 0 {
+                        indices.log_backlink(ie, anchor);
+                    }
+                }
+
+                // We keep the line of the first reference for diagnostics.
+                let (cur_flags, _) = index_refs.entry(ie).or_insert((0, lineno));
                 *cur_flags |= flags;
             }
 
@@ -927,20 +2850,84 @@ fn load_metadata(
                 plain,
             } => {
                 if let Err(e) = indices.reference(index, entry) {
-                    tt_warning!(status, "couldn't define entry `{}` in index `{}`", entry, index; e);
+                    metadata_warning(
+                        warnings,
+                        input_path,
+                        lineno,
+                        format!("couldn't define entry `{}` in index `{}`", entry, index),
+                        Some(e),
+                    );
                     continue;
                 }
 
                 let text = EntryText {
-                    tex: tex.to_owned(),
-                    plain: plain.to_owned(),
+                    tex: tex.to_string(),
+                    plain: plain.to_string(),
                 };
 
                 if let Err(e) = indices.define_text(index, entry, text) {
                     // The error here will contain the contextual information.
-                    tt_warning!(status, "couldn't define the text of an index entry"; e);
+                    metadata_warning(
+                        warnings,
+                        input_path,
+                        lineno,
+                        "couldn't define the text of an index entry",
+                        Some(e),
+                    );
                 }
             }
+
+            Metadatum::Tag(tag) => {
+                let co = match cur_output.as_ref() {
+                    Some(o) => *o,
+                    None if in_invalid_output => continue,
+                    None => {
+                        metadata_warning(
+                            warnings,
+                            input_path,
+                            lineno,
+                            format!(
+                                "attempt to use tag `{}` before an output has been specified",
+                                tag
+                            ),
+                            None,
+                        );
+                        continue;
+                    }
+                };
+
+                let valid = !tag.is_empty()
+                    && tag
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+
+                if !valid {
+                    metadata_warning(warnings, input_path, lineno, format!("ignoring invalid tag `{}`: tags may only contain ASCII letters, digits, `-`, and `_`", tag), None);
+                    continue;
+                }
+
+                indices.log_tag(tag.as_ref(), co);
+            }
+
+            Metadatum::IndexAlias {
+                index,
+                alias,
+                target,
+            } => {
+                if let Err(e) = indices.define_alias(index, alias, target, input_id, lineno) {
+                    // The error here will contain the contextual information.
+                    metadata_warning(
+                        warnings,
+                        input_path,
+                        lineno,
+                        "couldn't define an index alias",
+                        Some(e),
+                    );
+                }
+            }
+
+            // These are consumed by the pass-1 driver and shouldn't get here.
+            Metadatum::Dependency { .. } => {}
         }
     }
 
@@ -948,18 +2935,761 @@ fn load_metadata(
         input_id,
         index_refs
             .into_iter()
-            .map(|((index, entry), flags)| IndexRef {
+            .map(|((index, entry), (flags, line))| IndexRef {
                 index,
                 entry,
                 flags,
+                line,
             }),
     ))
 }
 
+// Snapshots of the constructed indices
+
+/// An entry ID in an [`IndexSnapshot`]: the `to_usize()` value of the ID at
+/// the time that the snapshot was made. Index IDs are stored the same way.
+type SnapId = u32;
+
+/// An [`InputLine`] in an [`IndexSnapshot`].
+type SnapInputLine = (SnapId, u32);
+
+/// An [`OutputLocation`] in an [`IndexSnapshot`], as its output and fragment.
+type SnapLocation = (SnapId, SnapId);
+
+/// An index and an entry within it, in an [`IndexSnapshot`].
+type SnapEntry = (SnapId, SnapId);
+
+/// An [`IndexRef`] in an [`IndexSnapshot`].
+type SnapIndexRef = (SnapId, SnapId, IndexRefFlags, u32);
+
+/// The state of an [`IndexCollection`] that is built up from the metadata of
+/// the inputs by [`construct_indices`], saved so that later builds can restore
+/// it instead of loading all of the metadata again. See
+/// [`crate::metadata_snapshot`] for how it's stored.
+///
+/// The build system populates the tables of input paths, output paths, and so
+/// on before the indices are constructed, and the IDs that it assigns vary
+/// from build to build. So, the snapshot records the names behind all of the
+/// IDs that it uses, and these are interned again when it's restored. The
+/// tables of paths only gain the names that are actually used, while the user
+/// and external indices are rebuilt in their original order.
+#[derive(Default, Deserialize, Serialize)]
+pub struct IndexSnapshot {
+    /// The state of each index, in order of ID.
+    indices: Vec<IndexSnapshotEntry>,
+
+    refs: Vec<(SnapId, Vec<SnapIndexRef>)>,
+    backlinks: Vec<(SnapEntry, Vec<SnapLocation>)>,
+    output_titles: Vec<(SnapId, SnapEntry)>,
+    output_sources: Vec<(SnapId, SnapInputLine)>,
+    duplicate_outputs: Vec<(SnapId, Vec<SnapInputLine>)>,
+    invalid_outputs: Vec<(SnapInputLine, String, String)>,
+    tags: Vec<(String, Vec<SnapId>)>,
+    duplicate_defs: Vec<(SnapEntry, Vec<(SnapInputLine, SnapLocation)>)>,
+
+    /// The warnings issued while the metadata were being loaded, which are
+    /// issued again when the snapshot is restored.
+    warnings: Vec<AlertMessage>,
+}
+
+/// The state of one index in an [`IndexSnapshot`].
+#[derive(Debug, Deserialize, Serialize)]
+struct IndexSnapshotEntry {
+    name: String,
+    entries: Vec<String>,
+    locs: Vec<Option<SnapLocation>>,
+    texts: Vec<Option<EntryText>>,
+    aliases: Vec<Option<SnapId>>,
+    def_inputs: Vec<Option<SnapInputLine>>,
+    alias_inputs: Vec<Option<SnapInputLine>>,
+    case_folds: Vec<(SnapId, SnapId)>,
+}
+
+fn snap_id(id: EntryId) -> SnapId {
+    id.to_usize() as SnapId
+}
+
+fn snap_entry((index, entry): (IndexId, EntryId)) -> SnapEntry {
+    (snap_id(index), snap_id(entry))
+}
+
+fn snap_location(loc: OutputLocation) -> SnapLocation {
+    (snap_id(loc.output), snap_id(loc.fragment))
+}
+
+fn snap_input_line((input, line): InputLine) -> SnapInputLine {
+    (snap_id(input), line)
+}
+
+/// Fill in a slot of one of the per-entry vectors of an [`Index`].
+fn fill_slot<T: Clone + Default + PartialEq>(v: &mut Vec<T>, entry: EntryId, value: T) {
+    let e = entry.to_usize();
+    let _ = v.ensure_holey_slot_available(e);
+    v[e] = value;
+}
+
+/// The translation of the IDs of an [`IndexSnapshot`] into the IDs of the
+/// collection that it's being restored into.
+struct SnapshotRemap {
+    /// For each index in the snapshot: its current ID, the names of its
+    /// entries, and their current IDs, for the ones that have been interned.
+    tables: Vec<(IndexId, Vec<String>, Vec<Option<EntryId>>)>,
+}
+
+impl SnapshotRemap {
+    fn entry(&mut self, index: SnapId, entry: SnapId, indices: &mut [Index]) -> Result<EntryId> {
+        let (cur, names, ids) = a_ok_or!(
+            self.tables.get_mut(index as usize);
+            ["the index snapshot refers to unknown index #{}", index]
+        );
+
+        let e = entry as usize;
+
+        if let Some(Some(id)) = ids.get(e) {
+            return Ok(*id);
+        }
+
+        let name = a_ok_or!(
+            names.get(e);
+            ["the index snapshot refers to unknown entry #{} of index #{}", entry, index]
+        );
+
+        let id = indices[cur.to_usize()].reference(name);
+        ids[e] = Some(id);
+        Ok(id)
+    }
+
+    fn index_entry(
+        &mut self,
+        (index, entry): SnapEntry,
+        indices: &mut [Index],
+    ) -> Result<(IndexId, EntryId)> {
+        let entry = self.entry(index, entry, indices)?;
+        Ok((self.tables[index as usize].0, entry))
+    }
+
+    fn location(&mut self, (o, f): SnapLocation, indices: &mut [Index]) -> Result<OutputLocation> {
+        Ok(OutputLocation::new(
+            self.entry(OUTPUTS_INDEX_INDEX as SnapId, o, indices)?,
+            self.entry(FRAGMENTS_INDEX_INDEX as SnapId, f, indices)?,
+        ))
+    }
+
+    fn input_line(
+        &mut self,
+        (input, line): SnapInputLine,
+        indices: &mut [Index],
+    ) -> Result<InputLine> {
+        Ok((
+            self.entry(INPUTS_INDEX_INDEX as SnapId, input, indices)?,
+            line,
+        ))
+    }
+}
+
+impl IndexCollection {
+    /// Capture the state built up from the metadata of the inputs in a
+    /// snapshot, along with the warnings that were issued while building it.
+    /// This should be called once the indices have been fully constructed,
+    /// before they're validated.
+    pub fn snapshot(&self, warnings: Vec<AlertMessage>) -> IndexSnapshot {
+        let ioi = &self.indices[INDEX_OF_INDICES_INDEX];
+
+        let indices = self
+            .indices
+            .iter()
+            .enumerate()
+            .map(|(i, idx)| {
+                let mut entries: Vec<_> = idx.iter().into_iter().collect();
+                entries.sort_by_key(|t| t.0);
+
+                IndexSnapshotEntry {
+                    name: ioi.resolve(EntryId::try_from_usize(i).unwrap()).to_owned(),
+                    entries: entries.into_iter().map(|t| t.1.to_owned()).collect(),
+                    locs: idx.locs.iter().map(|l| l.map(snap_location)).collect(),
+                    texts: idx.texts.clone(),
+                    aliases: idx.aliases.iter().map(|a| a.map(snap_id)).collect(),
+                    def_inputs: idx
+                        .def_inputs
+                        .iter()
+                        .map(|d| d.map(snap_input_line))
+                        .collect(),
+                    alias_inputs: idx
+                        .alias_inputs
+                        .iter()
+                        .map(|d| d.map(snap_input_line))
+                        .collect(),
+                    case_folds: idx
+                        .case_folds
+                        .iter()
+                        .map(|(e, t)| (snap_id(*e), snap_id(*t)))
+                        .collect(),
+                }
+            })
+            .collect();
+
+        let refs = self.indices[INPUTS_INDEX_INDEX]
+            .iter()
+            .into_iter()
+            .filter_map(|(input, _)| {
+                let refs = self.refs.lookup(input.to_usize()).ok()?;
+                let refs = refs
+                    .iter()
+                    .map(|r| (snap_id(r.index), snap_id(r.entry), r.flags, r.line))
+                    .collect();
+                Some((snap_id(input), refs))
+            })
+            .collect();
+
+        IndexSnapshot {
+            indices,
+            refs,
+            backlinks: self
+                .backlinks
+                .iter()
+                .map(|(target, locs)| {
+                    let locs = locs.iter().map(|l| snap_location(*l)).collect();
+                    (snap_entry(*target), locs)
+                })
+                .collect(),
+            output_titles: self
+                .output_titles
+                .iter()
+                .map(|(o, title)| (snap_id(*o), snap_entry(*title)))
+                .collect(),
+            output_sources: self
+                .output_sources
+                .iter()
+                .map(|(o, il)| (snap_id(*o), snap_input_line(*il)))
+                .collect(),
+            duplicate_outputs: self
+                .duplicate_outputs
+                .iter()
+                .map(|(o, ils)| {
+                    let ils = ils.iter().map(|il| snap_input_line(*il)).collect();
+                    (snap_id(*o), ils)
+                })
+                .collect(),
+            invalid_outputs: self
+                .invalid_outputs
+                .iter()
+                .map(|(il, path, problem)| (snap_input_line(*il), path.clone(), problem.clone()))
+                .collect(),
+            tags: self
+                .tags
+                .iter()
+                .map(|(tag, outputs)| (tag.clone(), outputs.iter().map(|o| snap_id(*o)).collect()))
+                .collect(),
+            duplicate_defs: self
+                .duplicate_defs
+                .iter()
+                .map(|(target, defs)| {
+                    let defs = defs
+                        .iter()
+                        .map(|(il, loc)| (snap_input_line(*il), snap_location(*loc)))
+                        .collect();
+                    (snap_entry(*target), defs)
+                })
+                .collect(),
+            warnings,
+        }
+    }
+
+    /// Restore the state captured in a snapshot, instead of constructing it
+    /// from the metadata of the inputs, returning the warnings that should be
+    /// issued again.
+    ///
+    /// The collection must not have had any metadata loaded into it. An error
+    /// arises if any of the indices in the snapshot aren't declared here, or if
+    /// the snapshot is inconsistent with itself. In that case, the collection
+    /// may have been partially modified, so callers should restore into a
+    /// copy that they can discard.
+    pub fn restore_snapshot(&mut self, mut snapshot: IndexSnapshot) -> Result<Vec<AlertMessage>> {
+        let mut remap = SnapshotRemap { tables: Vec::new() };
+
+        for snap in &mut snapshot.indices {
+            let id = self.get_index(&snap.name)?;
+            let ids = vec![None; snap.entries.len()];
+            remap
+                .tables
+                .push((id, std::mem::take(&mut snap.entries), ids));
+        }
+
+        // Rebuild the user and external indices in their original order.
+
+        for i in FRAGMENTS_INDEX_INDEX + 1..remap.tables.len() {
+            for e in 0..remap.tables[i].1.len() {
+                remap.entry(i as SnapId, e as SnapId, &mut self.indices)?;
+            }
+        }
+
+        // The per-entry state of each index.
+
+        for (i, snap) in snapshot.indices.into_iter().enumerate() {
+            let i = i as SnapId;
+            let cur = remap.tables[i as usize].0.to_usize();
+
+            for (e, loc) in snap.locs.into_iter().enumerate() {
+                if let Some(loc) = loc {
+                    let e = remap.entry(i, e as SnapId, &mut self.indices)?;
+                    let loc = remap.location(loc, &mut self.indices)?;
+                    fill_slot(&mut self.indices[cur].locs, e, Some(loc));
+                }
+            }
+
+            for (e, text) in snap.texts.into_iter().enumerate() {
+                if text.is_some() {
+                    let e = remap.entry(i, e as SnapId, &mut self.indices)?;
+                    fill_slot(&mut self.indices[cur].texts, e, text);
+                }
+            }
+
+            for (e, target) in snap.aliases.into_iter().enumerate() {
+                if let Some(target) = target {
+                    let e = remap.entry(i, e as SnapId, &mut self.indices)?;
+                    let target = remap.entry(i, target, &mut self.indices)?;
+                    fill_slot(&mut self.indices[cur].aliases, e, Some(target));
+                }
+            }
+
+            for (e, il) in snap.def_inputs.into_iter().enumerate() {
+                if let Some(il) = il {
+                    let e = remap.entry(i, e as SnapId, &mut self.indices)?;
+                    let il = remap.input_line(il, &mut self.indices)?;
+                    fill_slot(&mut self.indices[cur].def_inputs, e, Some(il));
+                }
+            }
+
+            for (e, il) in snap.alias_inputs.into_iter().enumerate() {
+                if let Some(il) = il {
+                    let e = remap.entry(i, e as SnapId, &mut self.indices)?;
+                    let il = remap.input_line(il, &mut self.indices)?;
+                    fill_slot(&mut self.indices[cur].alias_inputs, e, Some(il));
+                }
+            }
+
+            for (e, target) in snap.case_folds {
+                let e = remap.entry(i, e, &mut self.indices)?;
+                let target = remap.entry(i, target, &mut self.indices)?;
+                self.indices[cur].case_folds.insert(e, target);
+            }
+        }
+
+        // Everything else.
+
+        for (input, refs) in snapshot.refs {
+            let input = remap.entry(INPUTS_INDEX_INDEX as SnapId, input, &mut self.indices)?;
+            let mut restored = Vec::with_capacity(refs.len());
+
+            for (index, entry, flags, line) in refs {
+                let (index, entry) = remap.index_entry((index, entry), &mut self.indices)?;
+                restored.push(IndexRef {
+                    index,
+                    entry,
+                    flags,
+                    line,
+                });
+            }
+
+            self.log_references(input, restored)?;
+        }
+
+        for (target, locs) in snapshot.backlinks {
+            let target = remap.index_entry(target, &mut self.indices)?;
+            let mut restored = Vec::with_capacity(locs.len());
+
+            for loc in locs {
+                restored.push(remap.location(loc, &mut self.indices)?);
+            }
+
+            self.backlinks.insert(target, restored);
+        }
+
+        for (output, title) in snapshot.output_titles {
+            let output = remap.entry(OUTPUTS_INDEX_INDEX as SnapId, output, &mut self.indices)?;
+            let title = remap.index_entry(title, &mut self.indices)?;
+            self.output_titles.insert(output, title);
+        }
+
+        for (output, il) in snapshot.output_sources {
+            let output = remap.entry(OUTPUTS_INDEX_INDEX as SnapId, output, &mut self.indices)?;
+            let il = remap.input_line(il, &mut self.indices)?;
+            self.output_sources.insert(output, il);
+        }
+
+        for (output, ils) in snapshot.duplicate_outputs {
+            let output = remap.entry(OUTPUTS_INDEX_INDEX as SnapId, output, &mut self.indices)?;
+            let mut restored = Vec::with_capacity(ils.len());
+
+            for il in ils {
+                restored.push(remap.input_line(il, &mut self.indices)?);
+            }
+
+            self.duplicate_outputs.insert(output, restored);
+        }
+
+        for (il, path, problem) in snapshot.invalid_outputs {
+            let il = remap.input_line(il, &mut self.indices)?;
+            self.invalid_outputs.push((il, path, problem));
+        }
+
+        for (tag, outputs) in snapshot.tags {
+            let mut restored = Vec::with_capacity(outputs.len());
+
+            for o in outputs {
+                restored.push(remap.entry(OUTPUTS_INDEX_INDEX as SnapId, o, &mut self.indices)?);
+            }
+
+            self.tags.insert(tag, restored);
+        }
+
+        for (target, defs) in snapshot.duplicate_defs {
+            let target = remap.index_entry(target, &mut self.indices)?;
+            let mut restored = Vec::with_capacity(defs.len());
+
+            for (il, loc) in defs {
+                let il = remap.input_line(il, &mut self.indices)?;
+                restored.push((il, remap.location(loc, &mut self.indices)?));
+            }
+
+            self.duplicate_defs.insert(target, restored);
+        }
+
+        Ok(snapshot.warnings)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("href", "href"), 0);
+        assert_eq!(edit_distance("hfer", "href"), 2);
+        assert_eq!(edit_distance("hre", "href"), 1);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("naïve", "naive"), 1);
+    }
+
+    #[test]
+    fn absolute_urls() {
+        assert!(is_absolute_url("https://example.com/a"));
+        assert!(is_absolute_url("https:example"));
+        assert!(is_absolute_url("mailto:someone@example.com"));
+        assert!(is_absolute_url("git+ssh://example.com/repo"));
+        assert!(is_absolute_url("//example.com/a"));
+        assert!(!is_absolute_url("a/b.html"));
+        assert!(!is_absolute_url("/a/b.html"));
+        assert!(!is_absolute_url("foo?x=://"));
+        assert!(!is_absolute_url("a/b:c"));
+        assert!(!is_absolute_url(":foo"));
+        assert!(!is_absolute_url("1http://example.com"));
+    }
+
+    #[test]
+    fn output_paths() {
+        let norm = |p| normalize_output_path(p).unwrap();
+        assert_eq!(norm("index.html"), "index.html");
+        assert_eq!(
+            norm("e/foo-bar_2.0~x/index.html"),
+            "e/foo-bar_2.0~x/index.html"
+        );
+        assert_eq!(norm("e/(backtick)/index.html"), "e/(backtick)/index.html");
+        assert_eq!(
+            norm("e/pedia@maybeEmit/index.html"),
+            "e/pedia@maybeEmit/index.html"
+        );
+        assert_eq!(norm("e/./index.html"), "e/index.html");
+        assert_eq!(norm("./e//index.html"), "e/index.html");
+        assert!(normalize_output_path("").is_err());
+        assert!(normalize_output_path(".").is_err());
+        assert!(normalize_output_path("/etc/passwd").is_err());
+        assert!(normalize_output_path("../index.html").is_err());
+        assert!(normalize_output_path("e/../../index.html").is_err());
+        assert!(normalize_output_path("e/").is_err());
+        assert!(normalize_output_path("e/a b/index.html").is_err());
+        assert!(normalize_output_path("e/a%20b/index.html").is_err());
+        assert!(normalize_output_path("e/a?b/index.html").is_err());
+        assert!(normalize_output_path("e\\..\\index.html").is_err());
+        assert!(normalize_output_path("C:/index.html").is_err());
+        assert!(normalize_output_path("e/naïve/index.html").is_err());
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        const META_A: &str = "% pedia-protocol 1
+% input a.tex
+1 \\output{a.html}
+2 \\idef{terms}{foo}{f}
+3 \\itext{terms}{foo}{Foo}{Foo}
+4 \\iref{terms}{bar}{lt}
+5 \\itag{x}
+6 \\idef{nope}{baz}{b}
+";
+
+        const META_B: &str = "% pedia-protocol 1
+% input b.tex
+1 \\output{b.html}
+2 \\idef{terms}{bar}{}
+3 \\itext{terms}{bar}{Bar}{Bar}
+4 \\iref{terms}{foo}{ltb}
+";
+
+        let mut orig = IndexCollection::new().unwrap();
+        orig.declare_index("terms").unwrap();
+        let mut warnings = Vec::new();
+        let mut inputs = Vec::new();
+
+        for text in [META_A, META_B] {
+            let meta = InputMetadata::parse(text).unwrap();
+            let (input, refs) = apply_metadata(&meta, &mut orig, &mut warnings).unwrap();
+            orig.log_references(input, refs).unwrap();
+            inputs.push(meta.input);
+        }
+
+        orig.fold_case();
+        orig.finalize_backlinks();
+        assert_eq!(warnings.len(), 1);
+
+        let data = bincode::serialize(&orig.snapshot(warnings)).unwrap();
+
+        // The restored collection assigns different IDs to the paths.
+
+        let mut restored = IndexCollection::new().unwrap();
+        restored.reference(INPUTS_INDEX_NAME, "z.tex").unwrap();
+        restored.reference(OUTPUTS_INDEX_NAME, "z.html").unwrap();
+        restored.declare_index("terms").unwrap();
+
+        let warnings = restored
+            .restore_snapshot(bincode::deserialize(&data).unwrap())
+            .unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, Some(6));
+
+        for input in inputs {
+            let a = orig.reference(INPUTS_INDEX_NAME, input).unwrap();
+            let b = restored.reference(INPUTS_INDEX_NAME, input).unwrap();
+            assert_ne!(a, b);
+            assert_eq!(
+                orig.get_resolved_reference_tex(a),
+                restored.get_resolved_reference_tex(b)
+            );
+        }
+
+        assert_eq!(
+            orig.write_backlinks(Vec::new()).unwrap(),
+            restored.write_backlinks(Vec::new()).unwrap()
+        );
+        assert_eq!(
+            orig.write_tag_members(Vec::new(), "x").unwrap(),
+            restored.write_tag_members(Vec::new(), "x").unwrap()
+        );
+
+        // Snapshots mentioning undeclared indices, or IDs that they don't
+        // name, can't be restored.
+
+        let mut other = IndexCollection::new().unwrap();
+        let snapshot = bincode::deserialize(&data).unwrap();
+        assert!(other.restore_snapshot(snapshot).is_err());
+
+        let mut other = IndexCollection::new().unwrap();
+        other.declare_index("terms").unwrap();
+        let mut snapshot: IndexSnapshot = bincode::deserialize(&data).unwrap();
+        snapshot.refs.push((999, Vec::new()));
+        assert!(other.restore_snapshot(snapshot).is_err());
+    }
+
+    /// Create a collection with a user index named `terms`, having the
+    /// specified settings.
+    fn with_terms_index(settings: &str) -> (IndexCollection, IndexId) {
+        let text = format!("[index]\nname = \"terms\"\n{}", settings);
+        let rec: syntax::Index = toml::from_str(&text).unwrap();
+        let mut coll = IndexCollection::new().unwrap();
+        let index = coll.declare_index("terms").unwrap();
+        let config = IndexConfig::new("idx/terms.toml".to_owned(), &rec).unwrap();
+        coll.configs.insert(index, config);
+        (coll, index)
+    }
+
+    #[test]
+    fn case_folded_references() {
+        for with_alias in [false, true] {
+            let (mut coll, index) = with_terms_index("case_insensitive = true");
+            let input = coll.reference(INPUTS_INDEX_NAME, "a.tex").unwrap();
+            let output = coll
+                .reference(OUTPUTS_INDEX_NAME, "e/foo/index.html")
+                .unwrap();
+            let loc = coll.make_location_by_id(output, "");
+            let foo = coll.define_loc("terms", "foo", loc, input, 1).unwrap();
+            let (_, upper) = coll.reference_to_entry("terms", "Foo").unwrap();
+
+            let alias = if with_alias {
+                Some(coll.define_alias("terms", "bar", "FOO", input, 2).unwrap())
+            } else {
+                None
+            };
+
+            coll.fold_case();
+            let idx = &coll.indices[index.to_usize()];
+            assert_eq!(idx.canonical(upper), Some(foo));
+            assert_eq!(coll.entry_url(index, upper).as_deref(), Some("e/foo/"));
+
+            if let Some(alias) = alias {
+                assert_eq!(idx.canonical(alias), Some(foo));
+            }
+        }
+    }
+
+    fn config(text: &str) -> Result<IndexConfig> {
+        let rec: syntax::Index = toml::from_str(text).unwrap();
+        IndexConfig::new("idx/terms.toml".to_owned(), &rec)
+    }
+
+    #[test]
+    fn config_validation() {
+        let cfg = config("[index]\nname = \"t\"\ntitle = \"Terms\"\nurl_prefix = \"e/\"").unwrap();
+        assert_eq!(cfg.title.as_deref(), Some("Terms"));
+        assert_eq!(cfg.url_prefix.as_deref(), Some("e/"));
+        assert_eq!(cfg.listing_title, None);
+
+        let cfg = config("[index]\nname = \"t\"\ntitle = \"Terms\"\n[listing]").unwrap();
+        assert_eq!(cfg.listing_title.as_deref(), Some("Terms"));
+
+        assert!(config("[index]\nname = \"t\"\ntitle = \" \"").is_err());
+        assert!(config("[index]\nname = \"t\"\nurl_prefix = \"e\"").is_err());
+        assert!(config("[index]\nname = \"t\"\nurl_prefix = \"/e/\"").is_err());
+        assert!(config("[index]\nname = \"t\"\nurl_prefix = \"e/../\"").is_err());
+        assert!(config("[index]\nname = \"t\"\n[listing]").is_err());
+        assert!(config(
+            "[index]\nname = \"t\"\nurl_prefix = \"e/\"\n[inventory]\npath = \"t.json\""
+        )
+        .is_err());
+        assert!(config(
+            "[index]\nname = \"t\"\ntitle = \"T\"\n[listing]\n[inventory]\npath = \"t.json\""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn settings_violations() {
+        let (mut coll, _) = with_terms_index("require_text = true\nurl_prefix = \"e/\"");
+        let a = coll.reference(INPUTS_INDEX_NAME, "a.tex").unwrap();
+        let b = coll.reference(INPUTS_INDEX_NAME, "b.tex").unwrap();
+
+        // Violates both settings.
+        let output = coll.reference(OUTPUTS_INDEX_NAME, "x/index.html").unwrap();
+        let loc = coll.make_location_by_id(output, "");
+        coll.define_loc("terms", "foo", loc, a, 1).unwrap();
+
+        // Violates neither.
+        let output = coll
+            .reference(OUTPUTS_INDEX_NAME, "e/bar/index.html")
+            .unwrap();
+        let loc = coll.make_location_by_id(output, "");
+        coll.define_loc("terms", "bar", loc, b, 1).unwrap();
+        let text = EntryText {
+            tex: "bar".to_owned(),
+            plain: "bar".to_owned(),
+        };
+        coll.define_text("terms", "bar", text).unwrap();
+
+        let (mut bus, _recv) = crate::messages::new_sync_bus_channel();
+
+        let failures = FailureLog::new(false);
+        let e = coll.validate_settings(&failures, &mut bus).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "2 index entries violate their index settings"
+        );
+        assert_eq!(failures.len(), 1);
+
+        let failures = FailureLog::new(true);
+        coll.validate_settings(&failures, &mut bus).unwrap();
+        assert_eq!(failures.len(), 1);
+    }
+
+    #[test]
+    fn alias_redirect_paths() {
+        let (mut coll, _) = with_terms_index("");
+        let input = coll.reference(INPUTS_INDEX_NAME, "a.tex").unwrap();
+        let output = coll
+            .reference(OUTPUTS_INDEX_NAME, "e/foo/index.html")
+            .unwrap();
+        let loc = coll.make_location_by_id(output, "");
+        coll.define_loc("terms", "foo", loc, input, 1).unwrap();
+
+        for alias in ["old", "foo bar", "a<b", "100%", ".", "a/b", ""] {
+            coll.define_alias("terms", alias, "foo", input, 2).unwrap();
+        }
+
+        let (mut bus, _recv) = crate::messages::new_sync_bus_channel();
+        let redirects = coll.alias_redirects(&mut bus);
+        let paths: Vec<_> = redirects.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(paths, ["e/old/index.html"]);
+        assert_eq!(redirects[0].url, "../../e/foo/");
+    }
+
+    #[test]
+    fn duplicate_outputs() {
+        let mut coll = IndexCollection::new().unwrap();
+        let a = coll.reference(INPUTS_INDEX_NAME, "a.tex").unwrap();
+        let b = coll.reference(INPUTS_INDEX_NAME, "b.tex").unwrap();
+        let c = coll.reference(INPUTS_INDEX_NAME, "c.tex").unwrap();
+        let x = coll.reference(OUTPUTS_INDEX_NAME, "x.html").unwrap();
+        let y = coll.reference(OUTPUTS_INDEX_NAME, "y.html").unwrap();
+
+        coll.log_output_source(x, a, 1);
+        coll.log_output_source(x, a, 2);
+        coll.log_output_source(x, b, 3);
+        coll.log_output_source(x, c, 4);
+        coll.log_output_source(x, c, 5);
+        coll.log_output_source(y, a, 6);
+        coll.log_output_source(y, b, 7);
+
+        let messages = |recv: &mut crate::messages::SyncMessageBusReceiver| {
+            let mut messages = Vec::new();
+
+            while let Some(msg) = recv.try_recv() {
+                if let crate::messages::Message::Error(alert) = msg {
+                    messages.push(alert.message);
+                }
+            }
+
+            messages
+        };
+
+        let failures = FailureLog::new(false);
+        let (mut bus, mut recv) = crate::messages::new_sync_bus_channel();
+        let e = coll.validate_outputs(&failures, &mut bus).unwrap_err();
+        assert_eq!(e.to_string(), "2 outputs declared by more than one input");
+        assert_eq!(failures.len(), 3);
+        assert_eq!(
+            messages(&mut recv),
+            [
+                "output `x.html` is declared by 3 inputs: `a.tex:1`, `b.tex:3`, `c.tex:4`, `c.tex:5`",
+                "output `y.html` is declared by 2 inputs: `a.tex:6`, `b.tex:7`",
+            ]
+        );
+
+        coll.log_invalid_output(a, 8, "../z.html", anyhow!("bad"));
+        let (mut bus, _recv) = crate::messages::new_sync_bus_channel();
+        let e = coll
+            .validate_outputs(&FailureLog::new(false), &mut bus)
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "1 invalid output path and 2 outputs declared by more than one input"
+        );
+
+        let (mut bus, _recv) = crate::messages::new_sync_bus_channel();
+        let failures = FailureLog::new(true);
+        coll.validate_outputs(&failures, &mut bus).unwrap();
+        assert_eq!(failures.len(), 3);
+    }
+
     #[test]
     fn output_location_option_size() {
         assert_eq!(std::mem::size_of::<Option<OutputLocation>>(), 8);
@@ -970,12 +3700,61 @@ mod syntax {
     use serde::Deserialize;
 
     #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
     pub struct Index {
         pub index: Header,
+        pub inventory: Option<Inventory>,
+        pub listing: Option<Listing>,
     }
 
     #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
     pub struct Header {
         pub name: String,
+
+        /// A human-readable title for the index.
+        pub title: Option<String>,
+
+        /// Whether every entry with a location must also have text.
+        #[serde(default)]
+        pub require_text: bool,
+
+        /// Whether references match entry names case-insensitively.
+        #[serde(default)]
+        pub case_insensitive: bool,
+
+        /// The prefix that the output paths of the entries must have.
+        pub url_prefix: Option<String>,
+
+        /// Whether to warn about entries that are never referenced.
+        #[serde(default)]
+        pub warn_unreferenced: bool,
+    }
+
+    /// An external inventory, from which the index is loaded rather than being
+    /// built from the TeX inputs.
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Inventory {
+        /// The path of the inventory file, relative to the project root.
+        pub path: String,
+
+        /// The base URL for any relative URLs in the inventory.
+        pub base_url: Option<String>,
+    }
+
+    /// An A–Z listing page of the index's entries.
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    pub struct Listing {
+        /// The title of the page. Defaults to the title of the index.
+        pub title: Option<String>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    pub struct InventoryEntry {
+        pub entry: String,
+        pub url: String,
+        pub text: Option<String>,
     }
 }
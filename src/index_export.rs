@@ -0,0 +1,243 @@
+// Copyright 2024 the Tectonic Project
+// Licensed under the MIT License
+
+//! Exporting the user indices in a machine-readable JSON format.
+//!
+//! The CSV files in `cache/idx/` are an internal detail of the build system.
+//! This module creates the file `build/indices.json.data`, which is a stable
+//! JSON representation of every user index, intended for use by the web
+//! frontend and external tools. (As with the search index, we give it a
+//! non-JSON extension so that Parcel.js doesn't try to inline it.)
+//!
+//! The format is:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "indices": {
+//!     "INDEX-NAME": [
+//!       {
+//!         "entry": "ENTRY-NAME",
+//!         "url": "e/foo/#frag",
+//!         "fragment": "#frag",
+//!         "tex": "\\TeX\\ markup",
+//!         "plain": "plain text"
+//!       }
+//!     ]
+//!   },
+//!   "titles": {
+//!     "INDEX-NAME": "Human-Readable Title"
+//!   }
+//! }
+//! ```
+//!
+//! Indices and their entries are sorted by name. The `url` is relative to the
+//! top of the output tree, with any trailing `index.html` removed, and includes
+//! the `fragment`, which is empty if the entry is defined at the top of its
+//! output. The `url` and `fragment` are `null` if the entry has no location;
+//! `tex` and `plain` are `null` if it has no text. Aliased entries are listed
+//! with the information of their targets. Only the indices that have titles
+//! in their definition files appear in `titles`.
+
+use serde::Serialize;
+use sha2::Digest;
+use std::{collections::BTreeMap, fs::File};
+use tectonic_errors::prelude::*;
+use tectonic_status_base::StatusBackend;
+
+use crate::{
+    cache::{Cache, OpCacheData},
+    index::IndexCollection,
+    operation::{DigestComputer, DigestData, OpOutputStream, RuntimeEntityIdent},
+};
+
+/// The version of the export format. This should be incremented if the format
+/// changes incompatibly.
+const EXPORT_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct Export {
+    version: u32,
+    indices: BTreeMap<String, Vec<ExportEntry>>,
+    titles: BTreeMap<String, String>,
+}
+
+#[derive(Serialize)]
+struct ExportEntry {
+    entry: String,
+    url: Option<String>,
+    fragment: Option<String>,
+    tex: Option<String>,
+    plain: Option<String>,
+}
+
+/// Compute the identifier of the index export operation.
+pub fn operation_id(indices: &mut IndexCollection) -> DigestData {
+    opid_and_inputs(indices).0
+}
+
+/// Compute the identifier of the index export operation, along with the names
+/// of the user indices and the identifiers of their CSV files.
+///
+/// The titles of the indices don't appear in the CSV files, so they're folded
+/// into the identifier.
+fn opid_and_inputs(
+    indices: &mut IndexCollection,
+) -> (DigestData, Vec<(String, RuntimeEntityIdent)>) {
+    let mut dc = DigestComputer::default();
+    dc.update("export_indices_v2");
+
+    let index_names: Vec<String> = indices
+        .user_index_names()
+        .into_iter()
+        .map(|n| n.to_owned())
+        .collect();
+
+    let mut inputs = Vec::new();
+
+    for name in index_names {
+        let input = RuntimeEntityIdent::new_other_file(format!("cache/idx/{}.csv", name), indices);
+        input.update_digest(&mut dc, indices);
+
+        let title = indices
+            .get_index(&name)
+            .ok()
+            .and_then(|i| indices.index_title(i));
+        dc.update(title.unwrap_or_default());
+
+        inputs.push((name, input));
+    }
+
+    (dc.finalize(), inputs)
+}
+
+/// Potentially emit the JSON export of the user indices.
+///
+/// The return value is a list of identifiers of any outputs that were modified
+/// during the build process.
+pub fn maybe_export_indices_operation(
+    cache: &mut Cache,
+    indices: &mut IndexCollection,
+    status: &mut dyn StatusBackend,
+) -> Result<Vec<RuntimeEntityIdent>> {
+    let mut modified = Vec::new();
+
+    // Set up the information about the operation. The CSV files of the user
+    // indices contain exactly what we need.
+
+    let (opid, inputs) = opid_and_inputs(indices);
+
+    let rerun_reason = atry!(
+        cache.operation_needs_rerun(&opid, "the index export", indices, status);
+        ["failed to probe cache for index export operation"]
+    );
+
+    if rerun_reason.is_none() {
+        return Ok(modified);
+    }
+
+    let mut ocd = OpCacheData::new(opid);
+
+    let mut export = Export {
+        version: EXPORT_FORMAT_VERSION,
+        indices: BTreeMap::new(),
+        titles: BTreeMap::new(),
+    };
+
+    for (name, input) in inputs {
+        ocd.add_input(input);
+
+        let csv_path = indices.path_for_runtime_ident(input).unwrap();
+        let csv_file = atry!(
+            File::open(&csv_path);
+            ["failed to open input `{}`", csv_path.display()]
+        );
+
+        let mut r = csv::Reader::from_reader(csv_file);
+        let mut entries = Vec::new();
+
+        for rec in r.records() {
+            let rec = atry!(
+                rec;
+                ["error reading input `{}`", csv_path.display()]
+            );
+
+            // See `Index::write()` for the columns.
+            let entry = rec.get(0).unwrap_or_default();
+            let loc_output = rec.get(1).unwrap_or_default();
+            let loc_fragment = rec.get(2).unwrap_or_default();
+            let text_tex = rec.get(3).unwrap_or_default();
+            let text_plain = rec.get(4).unwrap_or_default();
+
+            let (url, fragment) = if loc_output.is_empty() {
+                (None, None)
+            } else {
+                let o = if loc_output.ends_with("/index.html") {
+                    &loc_output[..loc_output.len() - 10]
+                } else {
+                    loc_output
+                };
+
+                (
+                    Some(format!("{}{}", o, loc_fragment)),
+                    Some(loc_fragment.to_owned()),
+                )
+            };
+
+            let (tex, plain) = if text_tex.is_empty() && text_plain.is_empty() {
+                (None, None)
+            } else {
+                (Some(text_tex.to_owned()), Some(text_plain.to_owned()))
+            };
+
+            entries.push(ExportEntry {
+                entry: entry.to_owned(),
+                url,
+                fragment,
+                tex,
+                plain,
+            });
+        }
+
+        if let Some(title) = indices
+            .get_index(&name)
+            .ok()
+            .and_then(|i| indices.index_title(i))
+        {
+            export.titles.insert(name.clone(), title.to_owned());
+        }
+
+        export.indices.insert(name, entries);
+    }
+
+    let output = RuntimeEntityIdent::new_other_file("build/indices.json.data", indices);
+    let orig_digest = cache.unconditional_entity(output, indices)?.value_digest;
+
+    let mut output_stream = atry!(
+        OpOutputStream::new(output, indices);
+        ["failed to open output file {:?}", output]
+    );
+
+    atry!(
+        serde_json::to_writer_pretty(&mut output_stream, &export);
+        ["error writing to output {:?}", output]
+    );
+
+    let (entity, size) = atry!(
+        output_stream.close();
+        ["failed to close output file {:?}", output]
+    );
+
+    ocd.add_output_with_value(output, entity.value_digest, size);
+
+    if entity.value_digest != orig_digest {
+        modified.push(output);
+    }
+
+    atry!(
+        cache.finalize_operation(ocd, indices);
+        ["failed to store caching information for index export operation"]
+    );
+
+    Ok(modified)
+}
@@ -0,0 +1,197 @@
+// Copyright 2024 the Tectonic Project
+// Licensed under the MIT License
+
+//! Creating A–Z listing pages for the user indices.
+//!
+//! An index gets a listing page if its definition file `idx/NAME.toml` has a
+//! `[listing]` section giving the page's title. The page lists every entry that
+//! has a location, sorted by its plain-text representation, grouped by initial
+//! letter, and linked to the entry's definition. Each page is its own
+//! operation, whose input is the CSV file of the index written during index
+//! construction.
+//!
+//! The TeX representations of the entries can only be rendered by the TeX
+//! passes, so the listings display the plain-text representations, falling
+//! back to the entry names for entries without text.
+
+use sha2::Digest;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Write as _,
+    fs::File,
+    path::Path,
+};
+use tectonic_errors::prelude::*;
+use tectonic_status_base::StatusBackend;
+
+use crate::{
+    cache::Cache,
+    html,
+    index::IndexCollection,
+    operation::{DigestComputer, DigestData, RuntimeEntityIdent},
+};
+
+/// The heading of the group of entries that don't start with a letter.
+const SYMBOLS_HEADING: &str = "Symbols";
+
+/// Get the output path of the listing page of the specified index.
+pub fn listing_output(index: &str) -> String {
+    format!("indices/{}/index.html", index)
+}
+
+/// Compute the identifier of the operation creating the listing page of the
+/// specified index, which has the specified title.
+pub fn operation_id(index: &str, title: &str, indices: &mut IndexCollection) -> DigestData {
+    let input = RuntimeEntityIdent::new_other_file(format!("cache/idx/{}.csv", index), indices);
+    page_opid(title, input, indices)
+}
+
+fn page_opid(title: &str, input: RuntimeEntityIdent, indices: &IndexCollection) -> DigestData {
+    let mut dc = DigestComputer::default();
+    dc.update("make_index_page_v1");
+    dc.update(title);
+    input.update_digest(&mut dc, indices);
+    dc.finalize()
+}
+
+/// Potentially emit the A–Z listing pages of the user indices.
+///
+/// The return value is a list of identifiers of any pages that were modified
+/// during the build process.
+///
+/// If *only* is specified, only the pages of the indices that it names are
+/// considered.
+pub fn maybe_make_index_pages_operations(
+    cache: &mut Cache,
+    indices: &mut IndexCollection,
+    only: Option<&HashSet<String>>,
+    status: &mut dyn StatusBackend,
+) -> Result<Vec<RuntimeEntityIdent>> {
+    let mut modified = Vec::new();
+
+    let listings: Vec<(String, String)> = indices
+        .listings()
+        .into_iter()
+        .filter(|(name, _)| only.map(|o| o.contains(*name)).unwrap_or(true))
+        .map(|(name, title)| (name.to_owned(), title.to_owned()))
+        .collect();
+
+    if listings.is_empty() {
+        return Ok(modified);
+    }
+
+    let mut template = html::Template::new(indices);
+
+    for (name, title) in &listings {
+        let input = RuntimeEntityIdent::new_other_file(format!("cache/idx/{}.csv", name), indices);
+        let desc = format!("the listing page of index `{}`", name);
+
+        let page = html::GeneratedPage {
+            opid: page_opid(title, input, indices),
+            desc: &desc,
+            input,
+            output: indices.make_output_file_ident(listing_output(name)),
+            title,
+            rel_top: "../../",
+        };
+        let output = page.output;
+
+        if html::maybe_make_page(page, &mut template, render_listing, cache, indices, status)? {
+            modified.push(output);
+        }
+    }
+
+    Ok(modified)
+}
+
+/// Render the HTML content of a listing page from the CSV file of an index.
+fn render_listing(csv_path: &Path) -> Result<String> {
+    let csv_file = atry!(
+        File::open(csv_path);
+        ["failed to open input `{}`", csv_path.display()]
+    );
+
+    // Map from group heading, to sort key, to (label, URL). Aliased entries
+    // are written with the same text and location as their targets, so keying
+    // on both the sort key and the URL lets us drop them.
+    let mut groups: BTreeMap<String, BTreeMap<(String, String), String>> = BTreeMap::new();
+    let mut r = csv::Reader::from_reader(csv_file);
+
+    for rec in r.records() {
+        let rec = atry!(
+            rec;
+            ["error reading input `{}`", csv_path.display()]
+        );
+
+        // See `Index::write()` for the columns.
+        let entry = rec.get(0).unwrap_or_default();
+        let loc_output = rec.get(1).unwrap_or_default();
+        let loc_fragment = rec.get(2).unwrap_or_default();
+        let text_plain = rec.get(4).unwrap_or_default();
+
+        if loc_output.is_empty() {
+            continue;
+        }
+
+        let label = if text_plain.is_empty() {
+            entry
+        } else {
+            text_plain
+        };
+
+        let o = loc_output.strip_suffix("index.html").unwrap_or(loc_output);
+        let url = format!("{}{}", o, loc_fragment);
+
+        let heading = match label.chars().find(|c| !c.is_whitespace()) {
+            Some(c) if c.is_alphabetic() => c.to_uppercase().collect(),
+            _ => SYMBOLS_HEADING.to_owned(),
+        };
+
+        groups
+            .entry(heading)
+            .or_default()
+            .insert((label.to_lowercase(), url), label.to_owned());
+    }
+
+    // Put the symbols first, then the letters.
+
+    let mut headings: Vec<&String> = groups.keys().collect();
+    headings.sort_by_key(|h| (h.as_str() != SYMBOLS_HEADING, h.as_str()));
+
+    let mut content = String::from("<p class=\"pedia-az-nav\">\n");
+
+    for (i, heading) in headings.iter().enumerate() {
+        let sep = if i + 1 < headings.len() { " ·" } else { "" };
+        writeln!(
+            content,
+            "<a href=\"#az-{}\">{}</a>{}",
+            html::escape(&heading.to_lowercase()),
+            html::escape(heading),
+            sep
+        )?;
+    }
+
+    content.push_str("</p>\n");
+
+    for heading in headings {
+        writeln!(
+            content,
+            "<h2 id=\"az-{}\">{}</h2>\n<ul>",
+            html::escape(&heading.to_lowercase()),
+            html::escape(heading)
+        )?;
+
+        for ((_, url), label) in &groups[heading] {
+            writeln!(
+                content,
+                "<li><a href=\"../../{}\">{}</a></li>",
+                html::escape(url),
+                html::escape(label)
+            )?;
+        }
+
+        content.push_str("</ul>\n");
+    }
+
+    Ok(content)
+}
@@ -1,6 +1,7 @@
 // Copyright 2022 the Tectonic Project
 // Licensed under the MIT License
 
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use tectonic_errors::prelude::*;
 use walkdir::{DirEntry, Error as WalkDirError, WalkDir};
 
@@ -67,3 +68,114 @@ fn is_tex_or_dir(entry: &DirEntry) -> bool {
             .map(|s| s.ends_with(".tex") && !s.starts_with('_'))
             .unwrap_or(false)
 }
+
+/// A selection of inputs, specified as paths or glob patterns relative to the
+/// project root.
+///
+/// A pattern without any glob metacharacters matches the input with that path,
+/// or, if it names a directory, all of the inputs inside it.
+#[derive(Debug)]
+pub struct InputFilter {
+    globs: GlobSet,
+    paths: Vec<String>,
+}
+
+impl InputFilter {
+    pub fn new(patterns: &[String]) -> Result<Self> {
+        let mut globs = GlobSetBuilder::new();
+        let mut paths = Vec::new();
+
+        for pattern in patterns {
+            let pattern = pattern.trim_start_matches("./");
+
+            if pattern.contains(['*', '?', '[', '{']) {
+                let glob = atry!(
+                    GlobBuilder::new(pattern).literal_separator(true).build();
+                    ["invalid input pattern `{}`", pattern]
+                );
+                globs.add(glob);
+            } else {
+                // The project root itself (`.` or `./`) is stored as an empty
+                // path, which selects everything.
+                let path = pattern.trim_end_matches('/');
+                let path = if path == "." { "" } else { path };
+                paths.push(path.to_owned());
+            }
+        }
+
+        let globs = atry!(
+            globs.build();
+            ["failed to compile the input patterns"]
+        );
+
+        Ok(InputFilter { globs, paths })
+    }
+
+    /// Test whether the input with the specified path, relative to the project
+    /// root, is selected.
+    pub fn matches(&self, relpath: &str) -> bool {
+        self.globs.is_match(relpath)
+            || self.paths.iter().any(|p| {
+                p.is_empty()
+                    || relpath
+                        .strip_prefix(p.as_str())
+                        .map(|rest| rest.is_empty() || rest.starts_with('/'))
+                        .unwrap_or(false)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(patterns: &[&str]) -> InputFilter {
+        let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+        InputFilter::new(&patterns).unwrap()
+    }
+
+    #[test]
+    fn paths() {
+        let f = filter(&["txt/concepts/", "./txt/a.tex"]);
+        assert!(f.matches("txt/concepts/b.tex"));
+        assert!(f.matches("txt/concepts/sub/c.tex"));
+        assert!(!f.matches("txt/concepts-old/b.tex"));
+        assert!(f.matches("txt/a.tex"));
+        assert!(!f.matches("txt/a.tex.bak"));
+        assert!(!f.matches("txt/b.tex"));
+
+        let f = filter(&["txt/concepts"]);
+        assert!(f.matches("txt/concepts/b.tex"));
+        assert!(!f.matches("txt/conceptsb.tex"));
+    }
+
+    #[test]
+    fn globs() {
+        let f = filter(&["txt/*.tex", "./txt/sub/{a,b}.tex"]);
+        assert!(f.matches("txt/a.tex"));
+        assert!(!f.matches("txt/sub/c.tex"));
+        assert!(f.matches("txt/sub/b.tex"));
+        assert!(!f.matches("txt/a.txt"));
+
+        let f = filter(&["txt/**/a.tex"]);
+        assert!(f.matches("txt/a.tex"));
+        assert!(f.matches("txt/x/y/a.tex"));
+        assert!(!f.matches("txt/x/b.tex"));
+
+        let patterns = vec!["txt/[a".to_owned()];
+        assert!(InputFilter::new(&patterns).is_err());
+    }
+
+    #[test]
+    fn root() {
+        for pattern in [".", "./", ".//"] {
+            let f = filter(&[pattern]);
+            assert!(f.matches("txt/a.tex"), "{}", pattern);
+            assert!(f.matches("txt/sub/b.tex"), "{}", pattern);
+        }
+
+        let f = filter(&["./txt"]);
+        assert!(f.matches("txt/a.tex"));
+        assert!(!f.matches("other/a.tex"));
+    }
+}
@@ -8,18 +8,27 @@ use tectonic_status_base::{ChatterLevel, StatusBackend};
 mod assets;
 mod build;
 mod cache;
+mod cache_cli;
 mod config;
 mod entrypoint_file;
+mod failures;
+mod graph;
 mod holey_vec;
+mod html;
 mod index;
+mod index_export;
+mod index_pages;
 mod inputs;
 mod messages;
 mod metadata;
+mod metadata_snapshot;
 mod multivec;
 mod operation;
 mod pass1;
 mod pass2;
+mod redirects;
 mod serve;
+mod tag_pages;
 mod tex_escape;
 #[macro_use]
 mod tex_pass;
@@ -64,7 +73,9 @@ impl ToplevelArgs {
                 return;
             }
 
+            Action::Cache(a) => a.exec(status.as_mut()),
             Action::FirstPassImpl(a) => a.exec(status.as_mut()),
+            Action::Graph(a) => a.exec(status.as_mut()),
             Action::SecondPassImpl(a) => a.exec(status.as_mut()),
             Action::Serve(a) => a.exec(status.as_mut()),
         };
@@ -79,7 +90,9 @@ impl ToplevelArgs {
 #[derive(Debug, Subcommand)]
 enum Action {
     Build(build::BuildArgs),
+    Cache(cache_cli::CacheArgs),
     FirstPassImpl(pass1::FirstPassImplArgs),
+    Graph(graph::GraphArgs),
     SecondPassImpl(pass2::SecondPassImplArgs),
     Serve(serve::ServeArgs),
 }
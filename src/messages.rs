@@ -121,6 +121,10 @@ pub struct AlertMessage {
     /// The source file that this message is associated with, if any
     pub file: Option<String>,
 
+    /// The line number within the source file that this message is
+    /// associated with, if known
+    pub line: Option<u32>,
+
     /// The essential message
     pub message: String,
 
@@ -136,6 +140,7 @@ impl AlertMessage {
     ) -> Self {
         let mut alert = AlertMessage {
             file: file.map(|t| t.to_string()),
+            line: None,
             message: message.to_string(),
             context: Default::default(),
         };
@@ -148,6 +153,25 @@ impl AlertMessage {
 
         alert
     }
+
+    /// Get a user-facing description of the location associated with this
+    /// message, of the form `file:line` or just `file`, if any.
+    pub fn location(&self) -> Option<String> {
+        match (&self.file, self.line) {
+            (Some(f), Some(l)) => Some(format!("{f}:{l}")),
+            (Some(f), None) => Some(f.clone()),
+            _ => None,
+        }
+    }
+
+    /// Get the essential message, prefixed with the location information if
+    /// there is any.
+    pub fn located_message(&self) -> String {
+        match self.location() {
+            Some(loc) => format!("{loc}: {}", self.message),
+            None => self.message.clone(),
+        }
+    }
 }
 
 #[derive(Clone, serde::Deserialize, serde::Serialize)]
@@ -226,7 +250,7 @@ impl MessageBus for CliStatusMessageBus {
             Message::Error(d) => {
                 let mut s = self.status.lock().unwrap();
 
-                tt_error!(s, "{}", d.message);
+                tt_error!(s, "{}", d.located_message());
 
                 for c in &d.context[..] {
                     tt_error!(s, "  {c}");
@@ -236,7 +260,7 @@ impl MessageBus for CliStatusMessageBus {
             Message::Note(d) => {
                 let mut s = self.status.lock().unwrap();
 
-                tt_note!(s, "{}", d.message);
+                tt_note!(s, "{}", d.located_message());
 
                 for c in &d.context[..] {
                     tt_note!(s, "  {c}");
@@ -246,7 +270,7 @@ impl MessageBus for CliStatusMessageBus {
             Message::Warning(d) => {
                 let mut s = self.status.lock().unwrap();
 
-                tt_warning!(s, "{}", d.message);
+                tt_warning!(s, "{}", d.located_message());
 
                 for c in &d.context[..] {
                     tt_warning!(s, "  {c}");
@@ -281,21 +305,44 @@ impl SyncMessageBusSender {
         self.tx.blocking_send(msg).unwrap();
     }
 
+    /// Post an error associated with a specific location in an input file. A
+    /// line number of zero means that the line is unknown.
     pub fn file_error<T1: ToString, T2: ToString>(
         &mut self,
         file: T1,
+        line: u32,
         message: T2,
-        err: Option<Error>,
+        context: Vec<String>,
     ) {
-        let alert = AlertMessage::new(Some(file), message, err);
+        let alert = AlertMessage {
+            file: Some(file.to_string()),
+            line: (line > 0).then_some(line),
+            message: message.to_string(),
+            context,
+        };
         self.post(Message::Error(alert))
     }
+
+    /// Post a warning associated with a specific location in an input file. A
+    /// line number of zero means that the line is unknown.
+    pub fn file_warning<T1: ToString, T2: ToString>(
+        &mut self,
+        file: T1,
+        line: u32,
+        message: T2,
+        err: Option<Error>,
+    ) {
+        let mut alert = AlertMessage::new(Some(file), message, err);
+        alert.line = (line > 0).then_some(line);
+        self.post(Message::Warning(alert))
+    }
 }
 
 impl StatusBackend for SyncMessageBusSender {
     fn report(&mut self, kind: MessageKind, args: std::fmt::Arguments<'_>, err: Option<&Error>) {
         let mut alert = AlertMessage {
             file: None,
+            line: None,
             message: format!("{}", args),
             context: Default::default(),
         };
@@ -318,6 +365,7 @@ impl StatusBackend for SyncMessageBusSender {
     fn dump_error_logs(&mut self, _output: &[u8]) {
         self.post(Message::Error(AlertMessage {
             file: None,
+            line: None,
             message: "(internal error: TeX error log should not get here)".into(),
             context: Default::default(),
         }));
@@ -334,6 +382,12 @@ impl SyncMessageBusReceiver {
             bus.post(msg).await;
         }
     }
+
+    /// Get the next message that has been posted, if there is one.
+    #[cfg(test)]
+    pub fn try_recv(&mut self) -> Option<Message> {
+        self.rx.try_recv().ok()
+    }
 }
 
 pub fn new_sync_bus_channel() -> (SyncMessageBusSender, SyncMessageBusReceiver) {
@@ -2,30 +2,93 @@
 // Licensed under the MIT License
 
 //! Metadata entries output to the `pedia.txt` file by the TeX passes.
-
+//!
+//! # The metadata protocol
+//!
+//! The `pedia.txt` file, and the `.meta` files derived from it by the first
+//! pass, follow a line-oriented protocol. The first line is a header declaring
+//! the version of the protocol, currently:
+//!
+//! ```text
+//! % pedia-protocol 1
+//! ```
+//!
+//! Unrecognized versions are rejected, so that changes to the TeX side of the
+//! protocol can't silently break the Rust side. Lines starting with `%` are
+//! comments or headers. Each other line is a record of the form:
+//!
+//! ```text
+//! LINENO \CSEQ{TERM1}{TERM2}...
+//! ```
+//!
+//! where `LINENO` is the number of the TeX input line that produced the record
+//! (see [`MetadataRecord`]) and `CSEQ` identifies the kind of record (see
+//! [`Metadatum`]). Inside of the terms, the characters `%`, `{`, and `}` must
+//! be escaped as `%25`, `%7B`, and `%7D`, respectively. More generally, `%`
+//! followed by two hexadecimal digits encodes the byte with that value, so
+//! writers may escape other characters as well, as long as the decoded result
+//! is valid UTF-8. Terms can therefore contain arbitrary text, such as the TeX
+//! code `\{`, which has an unbalanced brace. For compatibility, balanced
+//! braces inside of terms are accepted without escaping.
+
+use serde::{Deserialize, Serialize};
+use std::{borrow::Cow, fmt};
 use tectonic_errors::prelude::*;
 
 use crate::index::{IndexRefFlag, IndexRefFlags};
 
+/// The version of the metadata protocol understood by this program.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The prefix of the header line that declares the protocol version.
+const PROTOCOL_HEADER_PREFIX: &str = "% pedia-protocol ";
+
+/// Get the header line declaring the current protocol version, without a
+/// trailing newline.
+pub fn protocol_header() -> String {
+    format!("{}{}", PROTOCOL_HEADER_PREFIX, PROTOCOL_VERSION)
+}
+
+/// Check that a line is a protocol header declaring a version that we
+/// understand.
+pub fn check_protocol_header(line: &str) -> Result<()> {
+    let version = a_ok_or!(
+        line.trim_end().strip_prefix(PROTOCOL_HEADER_PREFIX);
+        ["missing metadata protocol header: expected `{}`, got {:?}", protocol_header(), line]
+    );
+
+    ensure!(
+        version == PROTOCOL_VERSION.to_string(),
+        "unsupported metadata protocol version `{}`: this program only supports version {}",
+        version,
+        PROTOCOL_VERSION
+    );
+
+    Ok(())
+}
+
 /// A metadata entry from the `pedia.txt` file.
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum Metadatum<'a> {
     /// Declare an output HTML file that is created by this input. The value is
-    /// the relative path of the output HTML file. without escaping.
-    Output(&'a str),
+    /// the relative path of the output HTML file.
+    Output(#[serde(borrow)] Cow<'a, str>),
 
     /// Define the location of an index entry.
     IndexDef {
         /// The name of the index for which this entry is being declared.
-        index: &'a str,
+        #[serde(borrow)]
+        index: Cow<'a, str>,
 
         /// The name of the entry being declared.
-        entry: &'a str,
+        #[serde(borrow)]
+        entry: Cow<'a, str>,
 
         /// The URL fragment specifying the location within the current output
         /// document that is best associated with this entry's definition. May
         /// be empty. For HTML, should otherwise have the form `"#frag"`.
-        fragment: &'a str,
+        #[serde(borrow)]
+        fragment: Cow<'a, str>,
     },
 
     /// Reference an index entry.
@@ -34,10 +97,12 @@ pub enum Metadatum<'a> {
     /// resolved value will be provided to the TeX code.
     IndexRef {
         /// The name of the index in which the entry is being referenced.
-        index: &'a str,
+        #[serde(borrow)]
+        index: Cow<'a, str>,
 
         /// The name of the entry being referenced.
-        entry: &'a str,
+        #[serde(borrow)]
+        entry: Cow<'a, str>,
 
         /// The kinds of resources required by this reference.
         flags: IndexRefFlags,
@@ -46,48 +111,101 @@ pub enum Metadatum<'a> {
     /// Define the primary textual representation associated with an index entry.
     IndexText {
         /// The name of the index for which this entry is being declared.
-        index: &'a str,
+        #[serde(borrow)]
+        index: Cow<'a, str>,
 
         /// The name of the entry being declared.
-        entry: &'a str,
+        #[serde(borrow)]
+        entry: Cow<'a, str>,
 
         /// The full TeX representation of the entry.
-        tex: &'a str,
+        #[serde(borrow)]
+        tex: Cow<'a, str>,
 
         /// The plain-text representation of the entry.
-        plain: &'a str,
+        #[serde(borrow)]
+        plain: Cow<'a, str>,
+    },
+
+    /// Declare that an index entry name is an alias for another entry.
+    ///
+    /// References to the alias are resolved using the location and text of the
+    /// target entry. This is useful for keeping old names working when entries
+    /// are renamed.
+    IndexAlias {
+        /// The name of the index in which the alias is being declared.
+        #[serde(borrow)]
+        index: Cow<'a, str>,
+
+        /// The name of the alias.
+        #[serde(borrow)]
+        alias: Cow<'a, str>,
+
+        /// The name of the entry that the alias refers to.
+        #[serde(borrow)]
+        target: Cow<'a, str>,
+    },
+
+    /// Tag the current output with a category, such as `"deprecated"`. Tag
+    /// names may only contain ASCII letters, digits, `-`, and `_`.
+    Tag(#[serde(borrow)] Cow<'a, str>),
+
+    /// Declare a file that was read while processing the input.
+    ///
+    /// These records are consumed by the TeX workers, which translate them into
+    /// cache inputs, so they are not expected to appear in the saved metadata
+    /// files.
+    Dependency {
+        /// The directory in which the file was found, as reported by TeX. May
+        /// be empty.
+        #[serde(borrow)]
+        dir: Cow<'a, str>,
+
+        /// The name of the file.
+        #[serde(borrow)]
+        name: Cow<'a, str>,
     },
 }
 
 impl<'a> Metadatum<'a> {
-    pub fn parse(s: &'a str) -> Result<Self> {
+    /// Parse a record that starts after `col0` characters of its line, so that
+    /// error messages can report accurate columns.
+    fn parse_at(s: &'a str, col0: usize) -> Result<Self> {
         // It seems that we can't use FromStr because we can't link up the
         // lifetime in the input argument here to the impl lifetime.
-        let (cseq, terms) = parse_cseq_line(s)?;
-        let terms: Result<Vec<_>> = terms.collect();
-        let terms = terms?;
+        let (cseq, terms) = parse_cseq_line(s, col0)?;
+        let mut decoded = Vec::new();
+
+        for term in terms {
+            let (col, term) = term?;
+            decoded.push(unescape_term(term, col)?);
+        }
+
+        let n = decoded.len();
+        let mut terms = decoded.into_iter();
+        let mut next = || terms.next().unwrap();
 
         match cseq {
             "output" => {
-                ensure!(terms.len() == 1, "malformed metadata line {:?}: \\output must be followed by exactly 1 braced term", s);
-                Ok(Metadatum::Output(terms[0]))
+                ensure!(n == 1, "malformed metadata line {:?}: \\output must be followed by exactly 1 braced term", s);
+                Ok(Metadatum::Output(next()))
             }
 
             "idef" => {
-                ensure!(terms.len() == 3, "malformed metadata line {:?}: \\idef must be followed by exactly 3 braced terms", s);
+                ensure!(n == 3, "malformed metadata line {:?}: \\idef must be followed by exactly 3 braced terms", s);
                 Ok(Metadatum::IndexDef {
-                    index: terms[0],
-                    entry: terms[1],
-                    fragment: terms[2],
+                    index: next(),
+                    entry: next(),
+                    fragment: next(),
                 })
             }
 
             "iref" => {
-                ensure!(terms.len() == 3, "malformed metadata line {:?}: \\iref must be followed by exactly 3 braced terms", s);
+                ensure!(n == 3, "malformed metadata line {:?}: \\iref must be followed by exactly 3 braced terms", s);
 
-                let index = terms[0];
-                let entry = terms[1];
-                let flags_term = terms[2];
+                let index = next();
+                let entry = next();
+                let flags_term = next();
                 let mut flags = 0;
 
                 if flags_term.contains('l') {
@@ -98,6 +216,10 @@ impl<'a> Metadatum<'a> {
                     flags |= IndexRefFlag::NeedsText as u8;
                 }
 
+                if flags_term.contains('b') {
+                    flags |= IndexRefFlag::NeedsBacklinks as u8;
+                }
+
                 Ok(Metadatum::IndexRef {
                     index,
                     entry,
@@ -106,12 +228,34 @@ impl<'a> Metadatum<'a> {
             }
 
             "itext" => {
-                ensure!(terms.len() == 4, "malformed metadata line {:?}: \\itext must be followed by exactly 4 braced terms", s);
+                ensure!(n == 4, "malformed metadata line {:?}: \\itext must be followed by exactly 4 braced terms", s);
                 Ok(Metadatum::IndexText {
-                    index: terms[0],
-                    entry: terms[1],
-                    tex: terms[2],
-                    plain: terms[3],
+                    index: next(),
+                    entry: next(),
+                    tex: next(),
+                    plain: next(),
+                })
+            }
+
+            "ialias" => {
+                ensure!(n == 3, "malformed metadata line {:?}: \\ialias must be followed by exactly 3 braced terms", s);
+                Ok(Metadatum::IndexAlias {
+                    index: next(),
+                    alias: next(),
+                    target: next(),
+                })
+            }
+
+            "itag" => {
+                ensure!(n == 1, "malformed metadata line {:?}: \\itag must be followed by exactly 1 braced term", s);
+                Ok(Metadatum::Tag(next()))
+            }
+
+            "dep" => {
+                ensure!(n == 2, "malformed metadata line {:?}: \\dep must be followed by exactly 2 braced terms", s);
+                Ok(Metadatum::Dependency {
+                    dir: next(),
+                    name: next(),
                 })
             }
 
@@ -122,14 +266,181 @@ impl<'a> Metadatum<'a> {
     }
 }
 
+/// Format the metadatum in the protocol syntax, escaping its terms.
+impl<'a> fmt::Display for Metadatum<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (cseq, terms): (_, Vec<&str>) = match self {
+            Metadatum::Output(p) => ("output", vec![p]),
+            Metadatum::IndexDef {
+                index,
+                entry,
+                fragment,
+            } => ("idef", vec![index, entry, fragment]),
+            Metadatum::IndexRef {
+                index,
+                entry,
+                flags,
+            } => {
+                let mut fl = String::new();
+
+                if flags & IndexRefFlag::NeedsLoc as u8 != 0 {
+                    fl.push('l');
+                }
+
+                if flags & IndexRefFlag::NeedsText as u8 != 0 {
+                    fl.push('t');
+                }
+
+                if flags & IndexRefFlag::NeedsBacklinks as u8 != 0 {
+                    fl.push('b');
+                }
+
+                return write!(
+                    f,
+                    "\\iref{{{}}}{{{}}}{{{}}}",
+                    escape_term(index),
+                    escape_term(entry),
+                    fl
+                );
+            }
+            Metadatum::IndexText {
+                index,
+                entry,
+                tex,
+                plain,
+            } => ("itext", vec![index, entry, tex, plain]),
+            Metadatum::IndexAlias {
+                index,
+                alias,
+                target,
+            } => ("ialias", vec![index, alias, target]),
+            Metadatum::Tag(t) => ("itag", vec![t]),
+            Metadatum::Dependency { dir, name } => ("dep", vec![dir, name]),
+        };
+
+        write!(f, "\\{}", cseq)?;
+
+        for t in terms {
+            write!(f, "{{{}}}", escape_term(t))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A line of the `pedia.txt` file: a [`Metadatum`] along with the number of the
+/// input line that was being processed when TeX emitted it.
+///
+/// On disk, the line number is a decimal prefix separated from the record by a
+/// space, as in `42 \output{foo.html}`. If the prefix is missing, the line
+/// number is taken to be zero, meaning "unknown".
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct MetadataRecord<'a> {
+    /// The line number in the TeX input file, or zero if unknown.
+    pub line: u32,
+
+    /// The metadatum itself.
+    #[serde(borrow)]
+    pub datum: Metadatum<'a>,
+}
+
+impl<'a> MetadataRecord<'a> {
+    pub fn parse(s: &'a str) -> Result<Self> {
+        let (line, rest, col0) = match s.split_once(' ') {
+            Some((n, rest)) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => {
+                let line = atry!(
+                    n.parse();
+                    ["column 1: malformed line number in metadata line {:?}", s]
+                );
+                (line, rest, n.len() + 1)
+            }
+
+            _ => (0, s, 0),
+        };
+
+        Ok(MetadataRecord {
+            line,
+            datum: Metadatum::parse_at(rest, col0)?,
+        })
+    }
+}
+
+/// Format the record in the protocol syntax.
+impl<'a> fmt::Display for MetadataRecord<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.line, self.datum)
+    }
+}
+
+/// Escape a term for inclusion in a metadata record.
+pub fn escape_term(s: &str) -> Cow<'_, str> {
+    if !s.contains(['%', '{', '}']) {
+        return Cow::Borrowed(s);
+    }
+
+    let mut r = String::with_capacity(s.len() + 8);
+
+    for c in s.chars() {
+        match c {
+            '%' => r.push_str("%25"),
+            '{' => r.push_str("%7B"),
+            '}' => r.push_str("%7D"),
+            c => r.push(c),
+        }
+    }
+
+    Cow::Owned(r)
+}
+
+/// Decode the escapes in a term of a metadata record. The term starts at the
+/// 1-based column `col` of its line, which is used for error messages.
+fn unescape_term(s: &str, col: usize) -> Result<Cow<'_, str>> {
+    if !s.contains('%') {
+        return Ok(Cow::Borrowed(s));
+    }
+
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut it = s.char_indices();
+
+    while let Some((i, c)) = it.next() {
+        if c != '%' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+
+        let hex = s.get(i + 1..i + 3).unwrap_or_default();
+        let ecol = col + s[..i].chars().count();
+
+        let b = a_ok_or!(
+            (hex.len() == 2).then(|| u8::from_str_radix(hex, 16).ok()).flatten();
+            ["column {}: `%` must be followed by two hexadecimal digits", ecol]
+        );
+
+        bytes.push(b);
+        it.next();
+        it.next();
+    }
+
+    String::from_utf8(bytes)
+        .map(Cow::Owned)
+        .map_err(|_| anyhow!("column {}: escaped term is not valid UTF-8", col))
+}
+
 /// Parse a string of the form `\CSEQ{A}{B}{C}` into the control sequence and an
-/// interator of the individual terms.
-fn parse_cseq_line(s: &str) -> Result<(&str, CseqLineTerms<'_>)> {
+/// interator of the individual terms, which are not unescaped. The string
+/// starts after `col0` characters of its line, which is used to report 1-based
+/// columns in error messages.
+fn parse_cseq_line(s: &str, col0: usize) -> Result<(&str, CseqLineTerms<'_>)> {
     let mut it = s.char_indices();
 
     match it.next() {
         Some((0, '\\')) => {}
-        _ => bail!("cseq-line {:?} did not start with `\\`", s),
+        _ => bail!(
+            "column {}: cseq-line {:?} did not start with `\\`",
+            col0 + 1,
+            s
+        ),
     };
 
     let cseq_end = loop {
@@ -144,6 +455,7 @@ fn parse_cseq_line(s: &str) -> Result<(&str, CseqLineTerms<'_>)> {
                         s,
                         it,
                         first_i0: None,
+                        col0,
                     },
                 ));
             }
@@ -156,6 +468,7 @@ fn parse_cseq_line(s: &str) -> Result<(&str, CseqLineTerms<'_>)> {
             s,
             it,
             first_i0: Some(cseq_end + 1),
+            col0,
         },
     ))
 }
@@ -163,16 +476,25 @@ fn parse_cseq_line(s: &str) -> Result<(&str, CseqLineTerms<'_>)> {
 /// Helper type for parsing a line of the form `\cseq{t1}{t2}{t3}` into the
 /// sequence of "terms" `t1`, `t2`, and `t3`. Nested braces are honored.
 /// Unexpected text before, after, or between the braced terms is an error.
+/// Each term is yielded along with the 1-based column at which it starts.
 struct CseqLineTerms<'a> {
     s: &'a str,
     it: std::str::CharIndices<'a>,
     first_i0: Option<usize>,
+    col0: usize,
+}
+
+impl<'a> CseqLineTerms<'a> {
+    /// Get the 1-based column of the character at byte index `i`.
+    fn column(&self, i: usize) -> usize {
+        self.col0 + self.s[..i].chars().count() + 1
+    }
 }
 
 impl<'a> Iterator for CseqLineTerms<'a> {
-    type Item = Result<&'a str>;
+    type Item = Result<(usize, &'a str)>;
 
-    fn next(&mut self) -> Option<Result<&'a str>> {
+    fn next(&mut self) -> Option<Self::Item> {
         // In steady-state at this point, the next character should either be a
         // `{`, or we're finished. But since we can only detect the end of the
         // "cseq" portion by reading the `{`, for the first term we start in the
@@ -184,10 +506,11 @@ impl<'a> Iterator for CseqLineTerms<'a> {
             match self.it.next() {
                 Some((i, '{')) => i + 1,
 
-                Some((i, _)) => {
+                Some((i, c)) => {
                     return Some(Err(anyhow!(
-                        "unexpected character between terms at index {} in cseq-line {:?}",
-                        i,
+                        "column {}: unexpected character `{}` between terms in cseq-line {:?}",
+                        self.column(i),
+                        c,
                         self.s
                     )))
                 }
@@ -218,14 +541,15 @@ impl<'a> Iterator for CseqLineTerms<'a> {
 
                 None => {
                     return Some(Err(anyhow!(
-                        "incomplete/unbalanced terms in cseq-line {:?}",
+                        "column {}: incomplete/unbalanced term in cseq-line {:?}",
+                        self.column(i0 - 1),
                         self.s
                     )))
                 }
             }
         };
 
-        Some(Ok(&self.s[i0..i1]))
+        Some(Ok((self.column(i0), &self.s[i0..i1])))
     }
 }
 
@@ -235,11 +559,11 @@ mod tests {
 
     #[test]
     fn cseq_line_1() {
-        assert!(parse_cseq_line("noslash").is_err());
+        assert!(parse_cseq_line("noslash", 0).is_err());
 
         fn parse_collect(s: &str) -> Result<(&str, Vec<&str>)> {
-            let (cs, terms) = parse_cseq_line(s)?;
-            let terms: Result<Vec<_>> = terms.collect();
+            let (cs, terms) = parse_cseq_line(s, 0)?;
+            let terms: Result<Vec<_>> = terms.map(|t| t.map(|(_, t)| t)).collect();
             let terms = terms?;
             Ok((cs, terms))
         }
@@ -267,4 +591,78 @@ mod tests {
         assert!(parse_collect("\\t{a}x{b}").is_err());
         assert!(parse_collect("\\t{a}{b}x").is_err());
     }
+
+    #[test]
+    fn record_line_numbers() {
+        let rec = MetadataRecord::parse("42 \\output{a b.html}").unwrap();
+        assert_eq!(rec.line, 42);
+        assert_eq!(rec.datum, Metadatum::Output("a b.html".into()));
+
+        let rec = MetadataRecord::parse("\\itag{x}").unwrap();
+        assert_eq!(rec.line, 0);
+        assert_eq!(rec.datum, Metadatum::Tag("x".into()));
+
+        assert!(MetadataRecord::parse("42 output{x}").is_err());
+        assert!(MetadataRecord::parse("99999999999 \\itag{x}").is_err());
+    }
+
+    #[test]
+    fn protocol_headers() {
+        assert!(check_protocol_header(&protocol_header()).is_ok());
+        assert!(check_protocol_header("% pedia-protocol 1\n").is_ok());
+        assert!(check_protocol_header("% pedia-protocol 999").is_err());
+        assert!(check_protocol_header("% input foo.tex").is_err());
+        assert!(check_protocol_header("1 \\itag{x}").is_err());
+    }
+
+    #[test]
+    fn escapes() {
+        let rec = MetadataRecord::parse("7 \\itext{terms}{%5C%7B}{\\%7B}{100%25}").unwrap();
+        assert_eq!(
+            rec.datum,
+            Metadatum::IndexText {
+                index: "terms".into(),
+                entry: "\\{".into(),
+                tex: "\\{".into(),
+                plain: "100%".into(),
+            }
+        );
+
+        // Errors should point at the right column, counting the line number.
+        let e = MetadataRecord::parse("12 \\itag{a%zz}").unwrap_err();
+        assert!(e.to_string().starts_with("column 11:"), "{}", e);
+
+        let e = MetadataRecord::parse("12 \\itag{a}x").unwrap_err();
+        assert!(e.to_string().starts_with("column 12:"), "{}", e);
+
+        let e = MetadataRecord::parse("\\itag{%}").unwrap_err();
+        assert!(e.to_string().starts_with("column 7:"), "{}", e);
+
+        assert!(MetadataRecord::parse("\\itag{%FF}").is_err());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn itext_round_trip(
+            line in 0u32..100000,
+            entry in "\\PC*",
+            tex in "\\PC*",
+            plain in "\\PC*",
+        ) {
+            let rec = MetadataRecord {
+                line,
+                datum: Metadatum::IndexText {
+                    index: "terms".into(),
+                    entry: entry.into(),
+                    tex: tex.into(),
+                    plain: plain.into(),
+                },
+            };
+
+            let text = rec.to_string();
+            proptest::prop_assert!(!text.contains('\n'));
+            let parsed = MetadataRecord::parse(&text).unwrap();
+            proptest::prop_assert_eq!(parsed, rec);
+        }
+    }
 }
@@ -0,0 +1,243 @@
+// Copyright 2024 the Tectonic Project
+// Licensed under the MIT License
+
+//! Snapshots of the index metadata, persisted between builds.
+//!
+//! Every build needs to reconstruct the complete [`IndexCollection`], since the
+//! second pass needs the resolved references of each input. When none of the
+//! inputs to the indices have changed, the collection built by the previous
+//! build is restored from an [`IndexSnapshot`], saved in
+//! `cache/idx/indices.snapshot`. The collection also interns all of the paths
+//! used by the build system, which are set up before the indices are
+//! constructed, so those tables are rebuilt from the names in the snapshot
+//! rather than being saved wholesale.
+//!
+//! Otherwise, the metadata of all of the inputs have to be loaded into the
+//! indices again. Rather than reopening and reparsing every `cache/pass1/*.meta`
+//! file each time, we also save the parsed records of all of the inputs in
+//! `cache/idx/metadata.snapshot`, keyed by the digests of the metadata files
+//! that they came from. Then only the metadata files whose digests aren't in
+//! that snapshot need to be parsed.
+//!
+//! [`IndexCollection`]: crate::index::IndexCollection
+
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use std::{collections::HashMap, fs, io::ErrorKind, path::Path};
+use tectonic_errors::{anyhow::Context, prelude::*};
+use tempfile::NamedTempFile;
+
+use crate::{
+    index::IndexSnapshot,
+    metadata::{check_protocol_header, MetadataRecord},
+    operation::{DigestComputer, DigestData},
+};
+
+/// The path of the snapshot file, relative to the project root.
+pub const SNAPSHOT_PATH: &str = "cache/idx/metadata.snapshot";
+
+/// The path of the snapshot file of the constructed indices, relative to the
+/// project root.
+pub const INDEX_SNAPSHOT_PATH: &str = "cache/idx/indices.snapshot";
+
+/// The version of the snapshot format. This should be incremented whenever the
+/// format changes, including changes to [`MetadataRecord`].
+const SNAPSHOT_VERSION: u32 = 2;
+
+/// The version of the format of the index snapshot file. This should be
+/// incremented whenever the format changes, including changes to
+/// [`IndexSnapshot`].
+const INDEX_SNAPSHOT_VERSION: u32 = 1;
+
+/// The parsed contents of one `.meta` file, as written by the first pass.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InputMetadata<'a> {
+    /// The path of the input that the metadata came from, relative to the
+    /// project root.
+    pub input: &'a str,
+
+    /// The metadata records, in order.
+    #[serde(borrow)]
+    pub records: Vec<MetadataRecord<'a>>,
+}
+
+impl<'a> InputMetadata<'a> {
+    /// Parse the text of a `.meta` file.
+    pub fn parse(text: &'a str) -> Result<Self> {
+        let mut lines = text.lines();
+        check_protocol_header(lines.next().unwrap_or_default())?;
+
+        let input = match lines.next().and_then(|l| l.strip_prefix("% input ")) {
+            Some(p) => p.trim_end(),
+            None => bail!("unexpected second line of metadata"),
+        };
+
+        let mut records = Vec::new();
+
+        for line in lines {
+            records.push(MetadataRecord::parse(line)?);
+        }
+
+        Ok(InputMetadata { input, records })
+    }
+}
+
+#[derive(Deserialize)]
+struct Snapshot<'a> {
+    version: u32,
+
+    #[serde(borrow)]
+    inputs: Vec<(DigestData, InputMetadata<'a>)>,
+}
+
+/// The serialization-side mirror of [`Snapshot`], which lets us avoid copying
+/// the records.
+#[derive(Serialize)]
+struct SnapshotRef<'a, 'b> {
+    version: u32,
+    inputs: Vec<(DigestData, &'b InputMetadata<'a>)>,
+}
+
+/// The contents of the index snapshot file.
+///
+/// The snapshot itself is stored as a nested bincode blob so that we can
+/// verify its checksum before decoding it. A truncated or otherwise damaged
+/// file could easily decode into a snapshot that doesn't make sense.
+#[derive(Deserialize, Serialize)]
+struct IndexSnapshotFile {
+    version: u32,
+
+    /// The digest of everything that went into the indices.
+    key: DigestData,
+
+    /// The digest of `data`.
+    checksum: DigestData,
+
+    data: Vec<u8>,
+}
+
+/// Read the raw data of the snapshot file. If it doesn't exist, the result is
+/// empty.
+pub fn read_snapshot(root: &Path) -> Result<Vec<u8>> {
+    let path = root.join(SNAPSHOT_PATH);
+
+    match fs::read(&path) {
+        Ok(b) => Ok(b),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).context(format!("failed to read `{}`", path.display())),
+    }
+}
+
+/// Decode the data of the snapshot file into a table of the metadata of the
+/// inputs, keyed by the digests of the metadata files. Empty data yield an
+/// empty table.
+pub fn decode_snapshot(data: &[u8]) -> Result<HashMap<DigestData, InputMetadata<'_>>> {
+    if data.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let snapshot: Snapshot = atry!(
+        bincode::deserialize(data);
+        ["failed to deserialize the metadata snapshot"]
+    );
+
+    ensure!(
+        snapshot.version == SNAPSHOT_VERSION,
+        "unsupported metadata snapshot version {} (expected {})",
+        snapshot.version,
+        SNAPSHOT_VERSION
+    );
+
+    Ok(snapshot.inputs.into_iter().collect())
+}
+
+/// Atomically write a new snapshot file containing the specified metadata.
+pub fn write_snapshot(root: &Path, inputs: Vec<(DigestData, &InputMetadata)>) -> Result<()> {
+    let snapshot = SnapshotRef {
+        version: SNAPSHOT_VERSION,
+        inputs,
+    };
+
+    write_atomically(&root.join(SNAPSHOT_PATH), &snapshot)
+}
+
+/// Read the index snapshot file, if it exists and was saved with the
+/// specified key. An error is returned if the file is unreadable or damaged.
+pub fn read_index_snapshot(root: &Path, key: &DigestData) -> Result<Option<IndexSnapshot>> {
+    let path = root.join(INDEX_SNAPSHOT_PATH);
+
+    let data = match fs::read(&path) {
+        Ok(b) => b,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e).context(format!("failed to read `{}`", path.display())),
+    };
+
+    let file: IndexSnapshotFile = atry!(
+        bincode::deserialize(&data);
+        ["failed to deserialize the index snapshot"]
+    );
+
+    ensure!(
+        file.version == INDEX_SNAPSHOT_VERSION,
+        "unsupported index snapshot version {} (expected {})",
+        file.version,
+        INDEX_SNAPSHOT_VERSION
+    );
+
+    if file.key != *key {
+        return Ok(None);
+    }
+
+    ensure!(
+        DigestComputer::digest(&file.data) == file.checksum,
+        "the index snapshot is corrupt"
+    );
+
+    Ok(Some(atry!(
+        bincode::deserialize(&file.data);
+        ["failed to deserialize the index snapshot"]
+    )))
+}
+
+/// Atomically write a new index snapshot file, saved with the specified key.
+pub fn write_index_snapshot(root: &Path, key: &DigestData, snapshot: &IndexSnapshot) -> Result<()> {
+    let data = atry!(
+        bincode::serialize(snapshot);
+        ["failed to serialize the index snapshot"]
+    );
+
+    let file = IndexSnapshotFile {
+        version: INDEX_SNAPSHOT_VERSION,
+        key: *key,
+        checksum: DigestComputer::digest(&data),
+        data,
+    };
+
+    write_atomically(&root.join(INDEX_SNAPSHOT_PATH), &file)
+}
+
+fn write_atomically<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let dir = path.parent().unwrap();
+
+    atry!(
+        fs::create_dir_all(dir);
+        ["failed to create directory `{}`", dir.display()]
+    );
+
+    let mut f = atry!(
+        NamedTempFile::new_in(dir);
+        ["failed to create temporary file in `{}`", dir.display()]
+    );
+
+    atry!(
+        bincode::serialize_into(&mut f, value);
+        ["failed to serialize bincode data into `{}`", path.display()]
+    );
+
+    atry!(
+        f.persist(path);
+        ["failed to persist temporary file to `{}`", path.display()]
+    );
+
+    Ok(())
+}
@@ -10,9 +10,10 @@
 //!
 //! - An **entity** is some thing that is a potential input or output of a build
 //!   operation. Most entities correspond to files on the filesystem, but other
-//!   entity types are possible (e.g., a build operation might depend on an
+//!   entity types are possible: a build operation might depend on an
 //!   environment variable, in the sense that the operation might produce a
-//!   different output if the variable changes).
+//!   different output if the variable changes, or on the versions of the tools
+//!   that it uses. Such entities can only be inputs.
 //! - A **digest** is a cryptographic digest of some byte sequence.
 //! - An **identity** uniquely identifies an entity. You can compute the digest of
 //!   an identity.
@@ -27,7 +28,7 @@ use generic_array::GenericArray;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::{
-    fs,
+    fmt, fs,
     io::{self, ErrorKind, Write},
     path::PathBuf,
 };
@@ -51,6 +52,9 @@ pub type DigestData = GenericArray<u8, <DigestComputer as OutputSizeUser>::Outpu
 /// A [`string_interner`] "symbol" used for our various paths.
 type PathId = InputId;
 
+/// A [`string_interner`] "symbol" used for the names of environment variables.
+type NameId = InputId;
+
 /// The unique identifier of a logical entity that can be an input or an output
 /// of a build operation, as can be serialized to persistent storage.
 ///
@@ -76,6 +80,18 @@ pub enum PersistEntityIdent {
     /// A file that does not belong to one of the other categories. Its path is
     /// relative to the project root.
     OtherFile(String),
+
+    /// An environment variable. The string value is its name. Its value may
+    /// be unset.
+    EnvironmentVariable(String),
+
+    /// The Tectonic bundle used by the TeX processing sessions. Its value
+    /// combines the digest of the bundle's contents with the name of the TeX
+    /// format used by the sessions.
+    TexBundle,
+
+    /// The build of this program, identified by the digest of its executable.
+    ProgramVersion,
 }
 
 impl PersistEntityIdent {
@@ -88,6 +104,7 @@ impl PersistEntityIdent {
     ///
     /// The corresponding function on [`RuntimeEntityIdent`] is attached to the
     /// [`IndexCollection`] type because it depends on its internal structure.
+    /// Entities that aren't files don't have paths, and yield errors.
     pub fn path(&self, root: PathBuf) -> Result<PathBuf> {
         let p = match self {
             PersistEntityIdent::TexSourceFile(relpath) => {
@@ -108,6 +125,8 @@ impl PersistEntityIdent {
                 p.push(relpath);
                 p
             }
+
+            _ => bail!("entity `{}` is not associated with a path", self),
         };
 
         Ok(p)
@@ -121,10 +140,15 @@ impl PersistEntityIdent {
     /// For identities corresponding to files, that simply means that the file
     /// exists. As with all such tests, we should keep in mind that there is
     /// potentially a race condition between this function and any subsequent
-    /// attempts to actually access the artifact.
+    /// attempts to actually access the artifact. The other kinds of entities
+    /// always exist, since their values can always be computed.
     pub fn artifact_exists(&self, root: PathBuf) -> Result<bool> {
-        // For now, all we have are files, so:
-        let p = self.path(root).unwrap();
+        let p = match self {
+            PersistEntityIdent::TexSourceFile(_)
+            | PersistEntityIdent::OutputFile(_)
+            | PersistEntityIdent::OtherFile(_) => self.path(root)?,
+            _ => return Ok(true),
+        };
 
         match fs::metadata(&p) {
             Ok(_) => Ok(true),
@@ -134,6 +158,21 @@ impl PersistEntityIdent {
     }
 }
 
+impl fmt::Display for PersistEntityIdent {
+    /// Display the identity of a file as its path relative to the project
+    /// root.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PersistEntityIdent::TexSourceFile(relpath) => write!(f, "{}", relpath),
+            PersistEntityIdent::OutputFile(relpath) => write!(f, "build/{}", relpath),
+            PersistEntityIdent::OtherFile(relpath) => write!(f, "{}", relpath),
+            PersistEntityIdent::EnvironmentVariable(name) => write!(f, "${}", name),
+            PersistEntityIdent::TexBundle => write!(f, "(TeX bundle)"),
+            PersistEntityIdent::ProgramVersion => write!(f, "(program version)"),
+        }
+    }
+}
+
 /// For our purposes, an entity is a tuple of its identity and the digest of
 /// its value. This form is one that can be serialized to persistent storage.
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -166,6 +205,15 @@ pub enum RuntimeEntityIdent {
     /// A file that does not belong to one of the other categories. The id
     /// resolves to a path relative to the project root.
     OtherFile(PathId),
+
+    /// An environment variable. The id resolves to its name.
+    EnvironmentVariable(NameId),
+
+    /// The Tectonic bundle used by the TeX processing sessions.
+    TexBundle,
+
+    /// The build of this program, identified by the digest of its executable.
+    ProgramVersion,
 }
 
 impl RuntimeEntityIdent {
@@ -194,6 +242,23 @@ impl RuntimeEntityIdent {
         indices.make_other_file_ident(relpath)
     }
 
+    /// Create a new entity for an environment variable.
+    ///
+    /// This needs to go through the index collection to intern the name.
+    pub fn new_environment_variable(name: impl AsRef<str>, indices: &mut IndexCollection) -> Self {
+        indices.make_environment_variable_ident(name)
+    }
+
+    /// Returns true if this entity is a file.
+    pub fn is_file(&self) -> bool {
+        matches!(
+            self,
+            RuntimeEntityIdent::TexSourceFile(_)
+                | RuntimeEntityIdent::OutputFile(_)
+                | RuntimeEntityIdent::OtherFile(_)
+        )
+    }
+
     /// Add information about this identity to a [`DigestComputer`].
     ///
     /// This is accomplished by computing the "persistent" version of this
@@ -297,7 +362,7 @@ impl OpOutputStream {
     }
 
     /// Get a displayable form of the path of this file.
-    pub fn display_path(&self) -> std::path::Display {
+    pub fn display_path(&self) -> std::path::Display<'_> {
         self.path.display()
     }
 }
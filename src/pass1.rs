@@ -7,6 +7,7 @@ use clap::Args;
 use digest::Digest;
 use futures::Future;
 use std::{
+    collections::BTreeSet,
     io::{BufRead, BufReader, Cursor, Write},
     path::PathBuf,
 };
@@ -28,10 +29,14 @@ use crate::{
     holey_vec::HoleyVec,
     index::IndexCollection,
     messages::{AlertMessage, Message},
+    metadata::{check_protocol_header, protocol_header, MetadataRecord, Metadatum},
     ogtry,
     operation::{DigestComputer, DigestData, OpOutputStream, RuntimeEntityIdent},
     ostry, stry,
-    tex_pass::{TexOperation, TexProcessor, WorkerDriver, WorkerError, WorkerResultExt},
+    tex_pass::{
+        add_tex_session_inputs, resolve_dependency, tex_build_date, TexOperation, TexProcessor,
+        WorkerDriver, WorkerError, WorkerResultExt, TEX_FORMAT_NAME,
+    },
 };
 
 /// This type manages the execution of the set of pass-1 TeX jobs.
@@ -50,19 +55,49 @@ impl Pass1Processor {
     /// IDs.
     ///
     /// The "holey vector" scheme is used to ensure that these vectors are
-    /// properly indexable by input IDs; in other words, they are sorted the
-    /// same as the input index.
+    /// sorted the same as the input index. In "keep going" mode, the files of
+    /// inputs that failed are missing, so the vectors can't be indexed by input
+    /// IDs.
     pub fn unpack(mut self) -> (Vec<RuntimeEntityIdent>, Vec<RuntimeEntityIdent>) {
-        // We only get to this point if all inputs process correctly, so we can
-        // safely unwrap our "holey" vecs.
-
-        let asset_files = self.asset_files.drain(..).map(|o| o.unwrap()).collect();
-        let metadata_files = self.metadata_files.drain(..).map(|o| o.unwrap()).collect();
+        let asset_files = self.asset_files.drain(..).flatten().collect();
+        let metadata_files = self.metadata_files.drain(..).flatten().collect();
 
         (asset_files, metadata_files)
     }
 }
 
+/// Compute the identifier of the first-pass operation for the specified
+/// input.
+pub fn operation_id(input: RuntimeEntityIdent, indices: &IndexCollection) -> DigestData {
+    let mut dc = DigestComputer::default();
+    dc.update("pass1_v5");
+    input.update_digest(&mut dc, indices);
+    dc.finalize()
+}
+
+/// Get the identifiers of the intermediate asset and metadata files created
+/// by the first pass for the specified input.
+pub fn intermediate_idents(
+    input: RuntimeEntityIdent,
+    indices: &mut IndexCollection,
+) -> (RuntimeEntityIdent, RuntimeEntityIdent) {
+    let stripped = {
+        let input_relpath = indices.relpath_for_tex_source(input).unwrap();
+        input_relpath
+            .strip_suffix(".tex")
+            .unwrap_or(input_relpath)
+            .to_owned()
+    };
+
+    let assets_id =
+        RuntimeEntityIdent::new_other_file(format!("cache/pass1/{stripped}.assets"), indices);
+
+    let metadata_id =
+        RuntimeEntityIdent::new_other_file(format!("cache/pass1/{stripped}.meta"), indices);
+
+    (assets_id, metadata_id)
+}
+
 impl TexProcessor for Pass1Processor {
     /// This type is sent to a worker thread to drive an actual TeX process and
     /// return any results that we care about at runtime.
@@ -78,27 +113,8 @@ impl TexProcessor for Pass1Processor {
         _cache: &mut Cache,
         indices: &mut IndexCollection,
     ) -> Result<Pass1OpInfo> {
-        // Generate the ID of this operation
-        let mut dc = DigestComputer::default();
-        dc.update("pass1_v2");
-        input.update_digest(&mut dc, indices);
-        let opid = dc.finalize();
-
-        // Figure out the output idents.
-
-        let stripped = {
-            let input_relpath = indices.relpath_for_tex_source(input).unwrap();
-            input_relpath
-                .strip_suffix(".tex")
-                .unwrap_or(input_relpath)
-                .to_owned()
-        };
-
-        let assets_id =
-            RuntimeEntityIdent::new_other_file(&format!("cache/pass1/{stripped}.assets"), indices);
-
-        let metadata_id =
-            RuntimeEntityIdent::new_other_file(&format!("cache/pass1/{stripped}.meta"), indices);
+        let opid = operation_id(input, indices);
+        let (assets_id, metadata_id) = intermediate_idents(input, indices);
 
         Ok(Pass1OpInfo {
             opid,
@@ -167,6 +183,7 @@ impl Pass1Driver {
 
         let mut cache_data = OpCacheData::new(opinfo.opid);
         cache_data.add_input(opinfo.input_id);
+        add_tex_session_inputs(&mut cache_data, indices);
 
         // We'll add the outputs to the cache data at the end of the operation,
         // so that we can give the cache a hint about their final size and
@@ -175,9 +192,10 @@ impl Pass1Driver {
         let assets = stry!(OpOutputStream::new(opinfo.assets_id, indices));
         let mut metadata = stry!(OpOutputStream::new(opinfo.metadata_id, indices));
 
-        // Log the path of the input file so downstream processes can easily associate
-        // the indexing data with it.
+        // Declare the protocol version, and log the path of the input file so
+        // downstream processes can easily associate the indexing data with it.
 
+        stry!(writeln!(metadata, "{}", protocol_header()));
         stry!(writeln!(
             metadata,
             "% input {}",
@@ -206,10 +224,11 @@ impl WorkerDriver for Pass1Driver {
         futures::future::ok(())
     }
 
-    // TODO: record additional inputs if/when they are detected
-
     fn process_output_record(&mut self, record: &str) -> Option<Message> {
-        if let Some(rest) = record.strip_prefix("assets ") {
+        if let Some(rest) = record.strip_prefix("dep ") {
+            self.cache_data.add_other_file_input(rest);
+            None
+        } else if let Some(rest) = record.strip_prefix("assets ") {
             if let Err(e) = writeln!(&mut self.assets, "{}", rest) {
                 let alert = AlertMessage::new(
                     Some(self.input_path.display()),
@@ -304,12 +323,12 @@ impl FirstPassImplArgs {
         let mut sess = ProcessingSessionBuilder::new_with_security(security);
         sess.primary_input_buffer(input.as_bytes())
             .tex_input_name("texput")
-            .build_date(std::time::SystemTime::now())
+            .build_date(gtry!(tex_build_date()))
             .bundle(ogtry!(config.default_bundle(false, status)))
-            .format_name("latex")
+            .format_name(TEX_FORMAT_NAME)
             .output_format(OutputFormat::Html)
             .do_not_write_output_files()
-            .filesystem_root(root)
+            .filesystem_root(&root)
             .unstables(unstables)
             .format_cache_path(ogtry!(config.format_cache_path()))
             .html_emit_files(false)
@@ -339,16 +358,43 @@ impl FirstPassImplArgs {
             println!("pedia:assets {}", line);
         }
 
-        // Print out the `pedia.txt` metadata file
+        // Print out the `pedia.txt` metadata file. Dependency records are
+        // split out since they're handled by the driver, not the indexer.
 
         let assets = stry!(files
             .remove("pedia.txt")
             .ok_or_else(|| anyhow!("no `pedia.txt` file output")));
         let assets = BufReader::new(Cursor::new(&assets.data));
+        let mut lines = assets.lines();
+        let mut deps = BTreeSet::new();
 
-        for line in assets.lines() {
+        // The protocol header is checked here, and regenerated by the driver,
+        // so it's not forwarded.
+
+        let header = stry!(lines
+            .next()
+            .unwrap_or_else(|| Ok(String::new()))
+            .context("error reading line of `pedia.txt` output"));
+        stry!(check_protocol_header(&header).context("invalid `pedia.txt` output"));
+
+        for (i, line) in lines.enumerate() {
             let line = stry!(line.context("error reading line of `pedia.txt` output"));
-            println!("pedia:meta {}", line);
+            let rec = stry!(MetadataRecord::parse(&line)
+                .with_context(|| format!("error parsing line {} of `pedia.txt` output", i + 2)));
+
+            if let Metadatum::Dependency { dir, name } = rec.datum {
+                if let Some(relpath) = resolve_dependency(&root, &dir, &name) {
+                    deps.insert(relpath);
+                }
+            } else {
+                println!("pedia:meta {}", line);
+            }
+        }
+
+        deps.remove(&self.tex_path);
+
+        for relpath in &deps {
+            println!("pedia:dep {}", relpath);
         }
 
         Ok(())
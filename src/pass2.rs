@@ -7,13 +7,12 @@ use clap::Args;
 use futures::{future, Future, FutureExt};
 use sha2::Digest;
 use std::{
-    collections::HashSet,
+    collections::BTreeSet,
     fmt::Write as FmtWrite,
     fs::File,
-    io::{BufRead, BufReader, Write},
+    io::{BufRead, BufReader, Cursor, Read, Write},
     path::PathBuf,
 };
-use string_interner::Symbol;
 use tectonic::{
     config::PersistentConfig,
     driver::{OutputFormat, PassSetting, ProcessingSessionBuilder},
@@ -30,31 +29,30 @@ use tokio_util::io::SyncIoBridge;
 use crate::{
     cache::{Cache, OpCacheData},
     gtry,
-    index::IndexCollection,
-    messages::Message,
-    metadata::Metadatum,
+    index::{normalize_output_path, IndexCollection},
+    messages::{AlertMessage, Message},
+    metadata::{MetadataRecord, Metadatum},
+    metadata_snapshot::InputMetadata,
     ogtry,
-    operation::{DigestComputer, DigestData, RuntimeEntity, RuntimeEntityIdent},
-    ostry,
-    tex_pass::{TexOperation, TexProcessor, WorkerDriver, WorkerError, WorkerResultExt},
+    operation::{DigestComputer, DigestData, OpOutputStream, RuntimeEntity, RuntimeEntityIdent},
+    ostry, pass1, stry,
+    tex_pass::{
+        add_tex_session_inputs, resolve_dependency, tex_build_date, TexOperation, TexProcessor,
+        WorkerDriver, WorkerError, WorkerResultExt, TEX_FORMAT_NAME,
+    },
 };
 
 #[derive(Debug)]
 pub struct Pass2Processor {
     merged_assets_id: RuntimeEntityIdent,
     assets: AssetSpecification,
-    metadata_ids: Vec<RuntimeEntityIdent>,
     n_outputs_total: usize,
     n_outputs_rerun: usize,
     potential_modified_outputs: Vec<RuntimeEntity>,
 }
 
 impl Pass2Processor {
-    pub fn new(
-        metadata_ids: Vec<RuntimeEntityIdent>,
-        merged_assets_id: RuntimeEntityIdent,
-        indices: &IndexCollection,
-    ) -> Result<Self> {
+    pub fn new(merged_assets_id: RuntimeEntityIdent, indices: &IndexCollection) -> Result<Self> {
         // Load the merged assets info, which every TeX job will share.
 
         let mut assets = AssetSpecification::default();
@@ -75,7 +73,6 @@ impl Pass2Processor {
         Ok(Pass2Processor {
             merged_assets_id,
             assets,
-            metadata_ids,
             n_outputs_total: 0,
             n_outputs_rerun: 0,
             potential_modified_outputs: Vec::new(),
@@ -113,30 +110,17 @@ impl TexProcessor for Pass2Processor {
         cache: &mut Cache,
         indices: &mut IndexCollection,
     ) -> Result<Pass2OpInfo> {
-        // The vector of metadata IDs is guaranteed to be sorted so that it can
-        // be indexed by input IDs, so:
-
-        let input_index = match input {
-            RuntimeEntityIdent::TexSourceFile(s) => s.to_usize(),
-            _ => unreachable!(),
-        };
-
-        let metadata_id = self.metadata_ids[input_index];
+        let (_, metadata_id) = pass1::intermediate_idents(input, indices);
 
         Pass2OpInfo::new(input, metadata_id, self.merged_assets_id, cache, indices)
     }
 
     fn make_worker(
         &mut self,
-        opinfo: Pass2OpInfo,
+        mut opinfo: Pass2OpInfo,
         indices: &mut IndexCollection,
     ) -> Result<Self::Worker, WorkerError<Error>> {
-        let input_id = match opinfo.tex_input_id {
-            RuntimeEntityIdent::TexSourceFile(s) => s,
-            _ => unreachable!(),
-        };
-
-        let rrtex = indices.get_resolved_reference_tex(input_id);
+        let rrtex = std::mem::take(&mut opinfo.resolved_ref_tex);
 
         Ok(Pass2Driver::new(
             opinfo,
@@ -165,7 +149,15 @@ pub struct Pass2OpInfo {
     tex_input_id: RuntimeEntityIdent,
     merged_assets_id: RuntimeEntityIdent,
     metadata_id: RuntimeEntityIdent,
-    index_ids: Vec<RuntimeEntityIdent>,
+    refs_id: RuntimeEntityIdent,
+
+    /// The external inventories from which this input's references are
+    /// resolved, if any.
+    inventory_ids: Vec<RuntimeEntityIdent>,
+
+    /// The TeX code defining the resolved index references used by this
+    /// input. It is handed off to the worker if the operation is rerun.
+    resolved_ref_tex: String,
 
     // Outputs
     /// The entity here encodes the identities of the outputs and their digests
@@ -175,6 +167,29 @@ pub struct Pass2OpInfo {
     html_outputs: Vec<RuntimeEntity>,
 }
 
+/// Compute the identifier of the second-pass operation for the specified
+/// input.
+pub fn operation_id(input: RuntimeEntityIdent, indices: &IndexCollection) -> DigestData {
+    let mut dc = DigestComputer::default();
+    dc.update("pass2_v5");
+    input.update_digest(&mut dc, indices);
+    dc.finalize()
+}
+
+/// Get the identifier of the intermediate file holding the resolved index
+/// references of the specified input.
+pub fn refs_ident(input: RuntimeEntityIdent, indices: &mut IndexCollection) -> RuntimeEntityIdent {
+    let stripped = {
+        let input_relpath = indices.relpath_for_tex_source(input).unwrap();
+        input_relpath
+            .strip_suffix(".tex")
+            .unwrap_or(input_relpath)
+            .to_owned()
+    };
+
+    RuntimeEntityIdent::new_other_file(format!("cache/pass2/{stripped}.refs"), indices)
+}
+
 impl Pass2OpInfo {
     fn new(
         input: RuntimeEntityIdent,
@@ -186,67 +201,87 @@ impl Pass2OpInfo {
         // Construct the operation ID. We depend on a variety inputs, but the
         // operation is uniquely identified by its TeX input.
 
-        let mut dc = DigestComputer::default();
-        dc.update("pass2_v1");
-        input.update_digest(&mut dc, indices);
-        let opid = dc.finalize();
+        let opid = operation_id(input, indices);
 
-        // We need to load the metadata file to know what indices we need and
-        // what HTML outputs will be created.
-        let mut index_names = HashSet::new();
+        // We need to load the metadata file to know what HTML outputs will be
+        // created.
         let mut html_outputs = Vec::new();
 
         let meta_path = indices.path_for_runtime_ident(metadata_id).unwrap();
 
-        let meta_file = atry!(
-            File::open(&meta_path);
-            ["failed to open input `{}`", meta_path.display()]
-        );
-
-        let mut meta_buf = BufReader::new(meta_file);
-
-        // The header "% input <relpath>" line
-        let mut context = String::new();
+        let mut meta_text = String::new();
         atry!(
-            meta_buf.read_line(&mut context);
+            File::open(&meta_path).and_then(|mut f| f.read_to_string(&mut meta_text));
             ["failed to read input `{}`", meta_path.display()]
         );
 
-        for line in meta_buf.lines() {
-            let line = atry!(
-                line;
-                ["failed to read input `{}`", meta_path.display()]
-            );
-
-            match Metadatum::parse(&line)? {
-                Metadatum::Output(path) => {
-                    let ident = RuntimeEntityIdent::new_output_file(path, indices);
-                    html_outputs.push(cache.unconditional_entity(ident, indices)?);
-                }
-
-                Metadatum::IndexRef { index, .. } => {
-                    index_names.insert(index.to_owned());
-                }
+        let meta = atry!(
+            InputMetadata::parse(&meta_text);
+            ["failed to parse input `{}`", meta_path.display()]
+        );
 
-                _ => {}
+        for rec in meta.records {
+            if let Metadatum::Output(path) = rec.datum {
+                // Inputs with invalid output paths never get this far.
+                let path = atry!(
+                    normalize_output_path(&path);
+                    ["invalid output path `{}` in `{}`", path, meta_path.display()]
+                );
+                let ident = RuntimeEntityIdent::new_output_file(path, indices);
+                html_outputs.push(cache.unconditional_entity(ident, indices)?);
             }
         }
 
-        let mut index_ids = Vec::new();
+        // Rather than depending on the complete indices, which change whenever
+        // any entry anywhere is added, we depend on the resolved references
+        // that this input actually uses. These are saved to a file so that they
+        // fit into the caching framework; it is only rewritten if its contents
+        // change, so that its cached digest remains valid.
+
+        let input_id = match input {
+            RuntimeEntityIdent::TexSourceFile(s) => s,
+            _ => unreachable!(),
+        };
+
+        let resolved_ref_tex = indices.get_resolved_reference_tex(input_id);
+
+        let refs_id = refs_ident(input, indices);
 
-        for index_name in index_names.drain() {
-            index_ids.push(RuntimeEntityIdent::new_other_file(
-                &format!("cache/idx/{}.csv", index_name),
-                indices,
-            ));
+        let mut dc = DigestComputer::default();
+        dc.update(&resolved_ref_tex);
+        let refs_digest = dc.finalize();
+
+        if cache.unconditional_entity(refs_id, indices)?.value_digest != refs_digest {
+            let mut stream = OpOutputStream::new(refs_id, indices)?;
+
+            atry!(
+                stream.write_all(resolved_ref_tex.as_bytes());
+                ["failed to write `{}`", stream.display_path()]
+            );
+
+            let (entity, size) = stream.close()?;
+            cache.update_known_file(&entity, size, indices)?;
         }
 
+        let inventory_paths: Vec<String> = indices
+            .inventory_paths_for_input(input_id)
+            .into_iter()
+            .map(|p| p.to_owned())
+            .collect();
+
+        let inventory_ids = inventory_paths
+            .into_iter()
+            .map(|p| RuntimeEntityIdent::new_other_file(p, indices))
+            .collect();
+
         Ok(Pass2OpInfo {
             opid,
             tex_input_id: input,
             merged_assets_id,
             metadata_id,
-            index_ids,
+            refs_id,
+            inventory_ids,
+            resolved_ref_tex,
             html_outputs,
         })
     }
@@ -280,11 +315,14 @@ impl Pass2Driver {
         cache_data.add_input(opinfo.tex_input_id);
         cache_data.add_input(opinfo.metadata_id);
         cache_data.add_input(opinfo.merged_assets_id);
+        cache_data.add_input(opinfo.refs_id);
 
-        for idxid in &opinfo.index_ids {
-            cache_data.add_input(*idxid);
+        for inventory in &opinfo.inventory_ids {
+            cache_data.add_input(*inventory);
         }
 
+        add_tex_session_inputs(&mut cache_data, indices);
+
         // These outputs are created by Tectonic, so we can't calculate their
         // digests as we go; so might as well register them now.
         for output in &opinfo.html_outputs {
@@ -330,10 +368,17 @@ impl WorkerDriver for Pass2Driver {
         })
     }
 
-    // TODO: record additional inputs if/when they are detected
-
-    fn process_output_record(&mut self, _record: &str) -> Option<Message> {
-        None
+    fn process_output_record(&mut self, record: &str) -> Option<Message> {
+        if let Some(rest) = record.strip_prefix("dep ") {
+            self.cache_data.add_other_file_input(rest);
+            None
+        } else {
+            Some(Message::Warning(AlertMessage::new(
+                Some(self.input_path.display()),
+                format!("unrecognized pass2 stdout record: {}", record),
+                None,
+            )))
+        }
     }
 
     fn finish(self) -> Result<(OpCacheData, Pass2OpInfo), WorkerError<Error>> {
@@ -418,9 +463,9 @@ impl SecondPassImplArgs {
         let mut sess = ProcessingSessionBuilder::new_with_security(security);
         sess.primary_input_buffer(input.as_bytes())
             .tex_input_name("texput")
-            .build_date(std::time::SystemTime::now())
+            .build_date(gtry!(tex_build_date()))
             .bundle(ogtry!(config.default_bundle(false, status)))
-            .format_name("latex")
+            .format_name(TEX_FORMAT_NAME)
             .output_format(OutputFormat::Html)
             .html_precomputed_assets(assets)
             .filesystem_root(&root)
@@ -435,20 +480,37 @@ impl SecondPassImplArgs {
         // Print more details in the error case here?
         ostry!(sess.run(status));
 
-        // We *could* print out the `pedia.txt` metadata file, but it's not
-        // currently needed.
-        //
-        //let mut files = sess.into_file_data();
-        //
-        //let assets = stry!(files
-        //    .remove("pedia.txt")
-        //    .ok_or_else(|| anyhow!("no `pedia.txt` file output")));
-        //let assets = BufReader::new(Cursor::new(&assets.data));
-        //
-        //for line in assets.lines() {
-        //    let line = stry!(line.context("error reading line of `pedia.txt` output"));
-        //    println!("pedia:meta {}", line);
-        //}
+        // The indexing information in the `pedia.txt` metadata file was
+        // already gathered in pass 1, but we need to report the files that
+        // were read during this pass.
+
+        let mut files = sess.into_file_data();
+
+        let meta = stry!(files
+            .remove("pedia.txt")
+            .ok_or_else(|| anyhow!("no `pedia.txt` file output")));
+        let meta = BufReader::new(Cursor::new(&meta.data));
+        let mut deps = BTreeSet::new();
+
+        for line in meta.lines() {
+            let line = stry!(line.context("error reading line of `pedia.txt` output"));
+
+            if let Ok(MetadataRecord {
+                datum: Metadatum::Dependency { dir, name },
+                ..
+            }) = MetadataRecord::parse(&line)
+            {
+                if let Some(relpath) = resolve_dependency(&root, &dir, &name) {
+                    deps.insert(relpath);
+                }
+            }
+        }
+
+        deps.remove(&self.tex_path);
+
+        for relpath in &deps {
+            println!("pedia:dep {}", relpath);
+        }
 
         Ok(())
     }
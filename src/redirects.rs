@@ -0,0 +1,127 @@
+// Copyright 2024 the Tectonic Project
+// Licensed under the MIT License
+
+//! Creating redirect HTML files for aliased index entries.
+//!
+//! When an entry is renamed, an alias declared with `\ialias` keeps references
+//! to its old name working inside the text. To keep links from the outside
+//! world working too, we emit small HTML files at the old output paths that
+//! redirect to the new ones.
+
+use sha2::Digest;
+use std::{fs::File, io::Write};
+use tectonic_errors::prelude::*;
+use tectonic_status_base::StatusBackend;
+
+use crate::{
+    cache::{Cache, OpCacheData},
+    html,
+    index::IndexCollection,
+    operation::{DigestComputer, DigestData, OpOutputStream, RuntimeEntityIdent},
+};
+
+/// Compute the identifier of the redirect creation operation.
+pub fn operation_id(indices: &mut IndexCollection) -> DigestData {
+    let mut dc = DigestComputer::default();
+    dc.update("make_redirects_v1");
+
+    let input = RuntimeEntityIdent::new_other_file("cache/idx/redirects.csv", indices);
+    input.update_digest(&mut dc, indices);
+
+    dc.finalize()
+}
+
+/// Potentially emit the redirect files for aliased entries.
+///
+/// The return value is a list of identifiers of any redirects that were
+/// modified during the build process.
+pub fn maybe_make_redirects_operation(
+    cache: &mut Cache,
+    indices: &mut IndexCollection,
+    status: &mut dyn StatusBackend,
+) -> Result<Vec<RuntimeEntityIdent>> {
+    let mut modified = Vec::new();
+
+    // Set up the information about the operation. The index construction
+    // step writes out exactly the information that we need.
+
+    let input = RuntimeEntityIdent::new_other_file("cache/idx/redirects.csv", indices);
+    let opid = operation_id(indices);
+
+    let rerun_reason = atry!(
+        cache.operation_needs_rerun(&opid, "the redirect creation", indices, status);
+        ["failed to probe cache for redirect creation operation"]
+    );
+
+    if rerun_reason.is_none() {
+        return Ok(modified);
+    }
+
+    let mut ocd = OpCacheData::new(opid);
+    ocd.add_input(input);
+
+    let csv_path = indices.path_for_runtime_ident(input).unwrap();
+    let csv_file = atry!(
+        File::open(&csv_path);
+        ["failed to open input `{}`", csv_path.display()]
+    );
+
+    let mut r = csv::Reader::from_reader(csv_file);
+
+    for rec in r.records() {
+        let rec = atry!(
+            rec;
+            ["error reading input `{}`", csv_path.display()]
+        );
+
+        let path = rec.get(3).unwrap_or_default();
+        let url = html::escape(rec.get(4).unwrap_or_default());
+
+        let output = indices.make_output_file_ident(path);
+        let orig_digest = cache.unconditional_entity(output, indices)?.value_digest;
+
+        let mut output_stream = atry!(
+            OpOutputStream::new(output, indices);
+            ["failed to open output file {:?}", output]
+        );
+
+        atry!(
+            writeln!(
+                output_stream,
+                "<!DOCTYPE html>\n\
+                <html>\n\
+                <head>\n\
+                <meta charset=\"utf-8\">\n\
+                <title>Redirecting…</title>\n\
+                <link rel=\"canonical\" href=\"{url}\">\n\
+                <meta http-equiv=\"refresh\" content=\"0; url={url}\">\n\
+                </head>\n\
+                <body>\n\
+                <p>This page has moved to <a href=\"{url}\">{url}</a>.</p>\n\
+                </body>\n\
+                </html>"
+            );
+            ["error writing to output {:?}", output]
+        );
+
+        let (entity, size) = atry!(
+            output_stream.close();
+            ["failed to close output file {:?}", output]
+        );
+
+        ocd.add_output_with_value(output, entity.value_digest, size);
+
+        if entity.value_digest != orig_digest {
+            modified.push(output);
+        }
+    }
+
+    // All done.
+
+    atry!(
+        cache.finalize_operation(ocd, indices);
+        ["failed to store caching information for redirect creation operation"]
+    );
+
+    Ok(modified)
+}
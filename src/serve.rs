@@ -249,7 +249,7 @@ impl ServeArgs {
                                 let t0 = Instant::now();
                                 let mut success = false;
 
-                                match build_through_index(n_workers, true, clients.clone()).await {
+                                match build_through_index(n_workers, true, false, None, false, clients.clone()).await {
                                     Ok(changed) => {
                                         if let Err(e) = update_serve_dir(changed) {
                                             clients.error::<String, _>(None, "unable to update `serve` directory".to_string(), Some(e)).await;
@@ -0,0 +1,166 @@
+// Copyright 2024 the Tectonic Project
+// Licensed under the MIT License
+
+//! Creating listing pages for the tags attached to outputs with `\itag`.
+//!
+//! We create one page per tag, listing the outputs with that tag, plus an
+//! index page listing all of the tags. Each page is its own operation, whose
+//! input is a CSV file written during index construction, so that it is only
+//! re-emitted when the set of outputs that it lists changes.
+
+use sha2::Digest;
+use std::{collections::HashSet, fs::File, path::Path};
+use tectonic_errors::prelude::*;
+use tectonic_status_base::StatusBackend;
+
+use crate::{
+    cache::Cache,
+    html,
+    index::IndexCollection,
+    operation::{DigestComputer, DigestData, RuntimeEntityIdent},
+};
+
+/// The output path of the tag index page.
+pub const TAG_INDEX_OUTPUT: &str = "tags/index.html";
+
+/// Get the output path of the listing page of the specified tag.
+pub fn tag_output(tag: &str) -> String {
+    format!("tags/{}/index.html", tag)
+}
+
+/// Compute the identifier of the operation creating the tag index page.
+pub fn index_operation_id(indices: &mut IndexCollection) -> DigestData {
+    let input = RuntimeEntityIdent::new_other_file("cache/idx/tags.csv", indices);
+    page_opid("make_tag_index_v1", input, indices)
+}
+
+/// Compute the identifier of the operation creating the listing page of the
+/// specified tag.
+pub fn page_operation_id(tag: &str, indices: &mut IndexCollection) -> DigestData {
+    let input = RuntimeEntityIdent::new_other_file(format!("cache/tags/{}.csv", tag), indices);
+    page_opid("make_tag_page_v1", input, indices)
+}
+
+fn page_opid(op_name: &str, input: RuntimeEntityIdent, indices: &IndexCollection) -> DigestData {
+    let mut dc = DigestComputer::default();
+    dc.update(op_name);
+    input.update_digest(&mut dc, indices);
+    dc.finalize()
+}
+
+/// Potentially emit the tag listing pages.
+///
+/// The return value is a list of identifiers of any pages that were modified
+/// during the build process.
+///
+/// If *only* is specified, only the pages of the tags that it names are
+/// considered, along with the tag index page.
+pub fn maybe_make_tag_pages_operations(
+    cache: &mut Cache,
+    indices: &mut IndexCollection,
+    only: Option<&HashSet<String>>,
+    status: &mut dyn StatusBackend,
+) -> Result<Vec<RuntimeEntityIdent>> {
+    let mut modified = Vec::new();
+
+    let tag_names: Vec<String> = indices
+        .tag_names()
+        .into_iter()
+        .map(|t| t.to_owned())
+        .collect();
+
+    if tag_names.is_empty() {
+        return Ok(modified);
+    }
+
+    let mut template = html::Template::new(indices);
+
+    // The tag index page.
+
+    let input = RuntimeEntityIdent::new_other_file("cache/idx/tags.csv", indices);
+
+    let page = html::GeneratedPage {
+        opid: page_opid("make_tag_index_v1", input, indices),
+        desc: "the tag index page",
+        input,
+        output: indices.make_output_file_ident(TAG_INDEX_OUTPUT),
+        title: "Tags",
+        rel_top: "../",
+    };
+    let output = page.output;
+
+    let render = |csv_path: &Path| {
+        render_list(csv_path, |rec| {
+            let tag = html::escape(rec.get(0).unwrap_or_default());
+            let n = rec.get(1).unwrap_or_default();
+            format!("<li><a href=\"{tag}/\">{tag}</a> ({n})</li>\n")
+        })
+    };
+
+    if html::maybe_make_page(page, &mut template, render, cache, indices, status)? {
+        modified.push(output);
+    }
+
+    // The individual tag pages.
+
+    for tag in &tag_names {
+        if only.map(|o| !o.contains(tag)).unwrap_or(false) {
+            continue;
+        }
+
+        let input = RuntimeEntityIdent::new_other_file(format!("cache/tags/{}.csv", tag), indices);
+        let title = format!("Tagged “{}”", tag);
+        let desc = format!("the tag page “{}”", tag);
+
+        let page = html::GeneratedPage {
+            opid: page_opid("make_tag_page_v1", input, indices),
+            desc: &desc,
+            input,
+            output: indices.make_output_file_ident(tag_output(tag)),
+            title: &title,
+            rel_top: "../../",
+        };
+        let output = page.output;
+
+        let render = |csv_path: &Path| {
+            render_list(csv_path, |rec| {
+                let url = html::escape(rec.get(1).unwrap_or_default());
+                let title = html::escape(rec.get(2).unwrap_or_default());
+                format!("<li><a href=\"../../{url}\">{title}</a></li>\n")
+            })
+        };
+
+        if html::maybe_make_page(page, &mut template, render, cache, indices, status)? {
+            modified.push(output);
+        }
+    }
+
+    Ok(modified)
+}
+
+/// Render an HTML list whose items are derived from the records of a CSV
+/// file.
+fn render_list<F>(csv_path: &Path, mut format_item: F) -> Result<String>
+where
+    F: FnMut(&csv::StringRecord) -> String,
+{
+    let csv_file = atry!(
+        File::open(csv_path);
+        ["failed to open input `{}`", csv_path.display()]
+    );
+
+    let mut content = String::from("<ul>\n");
+    let mut r = csv::Reader::from_reader(csv_file);
+
+    for rec in r.records() {
+        let rec = atry!(
+            rec;
+            ["error reading input `{}`", csv_path.display()]
+        );
+
+        content.push_str(&format_item(&rec));
+    }
+
+    content.push_str("</ul>\n");
+    Ok(content)
+}
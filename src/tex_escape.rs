@@ -75,7 +75,7 @@ macro_rules! encode_impl {
         ///
         $(#[$attr])*
         #[inline]
-        pub fn $encode_name<S: ?Sized + AsRef<str>>(text: &S) -> Cow<str> {
+        pub fn $encode_name<S: ?Sized + AsRef<str>>(text: &S) -> Cow<'_, str> {
             let text = text.as_ref();
             let text_bytes = text.as_bytes();
             let text_length = text_bytes.len();
@@ -170,3 +170,32 @@ encode_impl! {
     /// Write text encoded for TeX inclusion to a writer.
     encode_tex_to_writer;
 }
+
+escape_impl! {
+    escape_tex_url;
+    b'#' => b"\\pediaHashChar ",
+    b'%' => b"\\pediaPercentChar ",
+    b'\\' => b"\\pediaPercentChar 5C",
+    b'{' => b"\\pediaPercentChar 7B",
+    b'}' => b"\\pediaPercentChar 7D",
+    b'^' => b"\\pediaPercentChar 5E",
+    b'~' => b"\\pediaPercentChar 7E",
+    b' ' => b"\\pediaPercentChar 20",
+}
+
+encode_impl! {
+    /// The characters `#` and `%` are replaced with the control sequences
+    /// `\pediaHashChar` and `\pediaPercentChar`, which expand to their
+    /// "other" versions, and the characters ` \{}^~` are percent-encoded. The
+    /// result can be used in macro definitions whose expansions end up in
+    /// `\special`s.
+    escape_tex_url;
+    /// Encode a URL for inclusion in TeX source.
+    encode_tex_url;
+    /// Write a URL encoded for TeX inclusion to a mutable `String` reference and return the encoded string slice.
+    encode_tex_url_to_string;
+    /// Write a URL encoded for TeX inclusion to a mutable `Vec<u8>` reference and return the encoded data slice.
+    encode_tex_url_to_vec;
+    /// Write a URL encoded for TeX inclusion to a writer.
+    encode_tex_url_to_writer;
+}
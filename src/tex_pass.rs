@@ -9,8 +9,15 @@
 //! - Subprocess stdout is parsed for information transfer
 
 use futures::Future;
-use std::{path::PathBuf, time::Instant};
+use sha2::Digest;
+use std::{
+    path::{Component, Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+use tectonic::{config::PersistentConfig, errors::SyncError, FORMAT_SERIAL};
+use tectonic_bundles::Bundle;
 use tectonic_errors::prelude::*;
+use tectonic_status_base::NoopStatusBackend;
 use tokio::{
     io::{AsyncBufReadExt, BufReader},
     process::{ChildStdin, Command},
@@ -20,12 +27,13 @@ use tokio_task_pool::Pool;
 
 use crate::{
     cache::{Cache, OpCacheData},
+    failures::FailureLog,
     index::IndexCollection,
     messages::{
         bus_to_status, AlertMessage, BuildCompleteMessage, BuildStartedMessage,
         InputDebugOutputMessage, Message, MessageBus,
     },
-    operation::{DigestData, RuntimeEntityIdent},
+    operation::{DigestComputer, DigestData, RuntimeEntityIdent},
 };
 
 #[derive(Debug)]
@@ -115,6 +123,111 @@ macro_rules! stry {
     };
 }
 
+/// The name of the TeX format used by all of our processing sessions.
+pub const TEX_FORMAT_NAME: &str = "latex";
+
+/// Environment variables that affect the TeX processing sessions.
+/// `SOURCE_DATE_EPOCH` overrides the build date.
+const TEX_ENVIRONMENT_VARIABLES: &[&str] = &["SOURCE_DATE_EPOCH"];
+
+/// Log the inputs that are common to all operations that run TeX processing
+/// sessions: the bundle and format, and the relevant environment variables.
+pub fn add_tex_session_inputs(ocd: &mut OpCacheData, indices: &mut IndexCollection) {
+    ocd.add_input(RuntimeEntityIdent::TexBundle);
+
+    for name in TEX_ENVIRONMENT_VARIABLES {
+        ocd.add_input(RuntimeEntityIdent::new_environment_variable(name, indices));
+    }
+}
+
+/// Determine the build date to use in the TeX processing sessions.
+///
+/// This honors `SOURCE_DATE_EPOCH` if it's set, and otherwise uses the
+/// current time. Tectonic panics on an invalid value of the variable, so we
+/// validate it here instead, and the build checks it before starting any
+/// workers.
+pub fn tex_build_date() -> Result<SystemTime> {
+    let Some(value) = std::env::var_os("SOURCE_DATE_EPOCH") else {
+        return Ok(SystemTime::now());
+    };
+
+    let secs = value
+        .to_str()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .ok_or_else(|| {
+            anyhow!(
+                "invalid `SOURCE_DATE_EPOCH` value `{}`: expected a nonnegative integer",
+                value.to_string_lossy()
+            )
+        })?;
+
+    Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Compute the value digest of the [`RuntimeEntityIdent::TexBundle`] entity,
+/// which combines the digest of the contents of the default bundle with the
+/// name and serial number of the TeX format.
+///
+/// This may need to access the network if the bundle isn't cached.
+pub fn tex_bundle_digest() -> Result<DigestData> {
+    let mut status = NoopStatusBackend::default();
+    let config = PersistentConfig::open(false).map_err(SyncError::new)?;
+    let mut bundle = config
+        .default_bundle(false, &mut status)
+        .map_err(SyncError::new)?;
+
+    let bundle_digest = atry!(
+        bundle.get_digest(&mut status);
+        ["failed to get the digest of the TeX bundle"]
+    );
+
+    let mut dc = DigestComputer::default();
+    dc.update(bundle_digest.to_string());
+    dc.update(TEX_FORMAT_NAME);
+    dc.update(FORMAT_SERIAL.to_le_bytes());
+    Ok(dc.finalize())
+}
+
+/// Resolve a file that the TeX engine reported reading into a path relative to
+/// the project root, if the file lives in the project tree.
+///
+/// The engine looks for files in the project root and then in `cls`, so we
+/// probe in that same order. Files that come from the bundle won't be found,
+/// and so `None` is returned for them; they can't change without the bundle
+/// changing, and the bundle is tracked as a whole by
+/// [`add_tex_session_inputs`].
+pub fn resolve_dependency(root: &Path, dir: &str, name: &str) -> Option<String> {
+    let mut rel = PathBuf::from(dir);
+    rel.push(name);
+
+    // Only accept plain relative paths so that we can't be tricked into
+    // tracking something outside of the tree.
+    if !rel
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return None;
+    }
+
+    for prefix in ["", "cls"] {
+        let mut candidate = PathBuf::from(prefix);
+        candidate.push(&rel);
+
+        if root.join(&candidate).is_file() {
+            let parts: Option<Vec<_>> = candidate
+                .components()
+                .filter_map(|c| match c {
+                    Component::Normal(s) => Some(s.to_str()),
+                    _ => None,
+                })
+                .collect();
+            return parts.map(|p| p.join("/"));
+        }
+    }
+
+    None
+}
+
 /// A type that can drive a TeX worker process.
 ///
 /// This type is created in the primary thread and sent to one of the task pool
@@ -400,12 +513,18 @@ pub trait TexOperation: Send {
     fn operation_ident(&self) -> DigestData;
 }
 
+/// Process a batch of inputs, returning the number of TeX jobs that were run.
+///
+/// Inputs that fail are recorded in *failures*. Unless it's in "keep going"
+/// mode, an error is returned if any inputs fail. An error is always returned
+/// if a problem occurs that isn't specific to a particular input.
 pub async fn process_inputs<'a, P: TexProcessor, B: MessageBus + 'static>(
     inputs: impl IntoIterator<Item = &'a RuntimeEntityIdent>,
     n_workers: usize,
     proc: &mut P,
     cache: &mut Cache,
     indices: &mut IndexCollection,
+    failures: &FailureLog,
     mut bus: B,
 ) -> Result<usize> {
     let self_path = atry!(
@@ -418,28 +537,40 @@ pub async fn process_inputs<'a, P: TexProcessor, B: MessageBus + 'static>(
     let (tx, mut rx) = channel(2 * n_workers);
     let mut n_tasks = 0;
     let mut n_failures = 0;
+    let mut gave_up = false;
 
-    for input in inputs {
-        let input = *input;
-        let input_path = indices.relpath_for_tex_source(input).unwrap().to_owned();
+    // Prepare all of the operations up front, so that the cache can check the
+    // freshness of all of their inputs in one parallelized batch.
+
+    let mut opinfos = Vec::new();
 
+    for input in inputs {
         // In principle this could/should be a WorkerError, but the distinction
         // doesn't seem super important.
         let opinfo = atry!(
-            proc.make_op_info(input, cache, indices);
-            ["failed to prepare operation for input `{}`", indices.relpath_for_tex_source(input).unwrap()]
+            proc.make_op_info(*input, cache, indices);
+            ["failed to prepare operation for input `{}`", indices.relpath_for_tex_source(*input).unwrap()]
         );
 
+        opinfos.push((*input, opinfo));
+    }
+
+    let opids: Vec<DigestData> = opinfos.iter().map(|o| o.1.operation_ident()).collect();
+    cache.prefetch_operations(&opids, indices);
+
+    for (input, opinfo) in opinfos {
+        let input_path = indices.relpath_for_tex_source(input).unwrap().to_owned();
         let opid = opinfo.operation_ident();
 
         // If the cache query fails, that's definitely something that should
         // cause us to bail immediately.
-        let needs_rerun = atry!(
-            bus_to_status(bus.clone(), |s| cache.operation_needs_rerun(&opid, indices, s)).await;
+        let what = format!("{} of `{}`", proc.description(), input_path);
+        let rerun_reason = atry!(
+            bus_to_status(bus.clone(), |s| cache.operation_needs_rerun(&opid, &what, indices, s)).await;
             ["failed to query build cache"]
         );
 
-        if !needs_rerun {
+        if rerun_reason.is_none() {
             bus.post(Message::BuildStarted(BuildStartedMessage {
                 file: Some(input_path.clone()),
             }))
@@ -447,6 +578,7 @@ pub async fn process_inputs<'a, P: TexProcessor, B: MessageBus + 'static>(
 
             bus.post(Message::Note(AlertMessage {
                 file: Some(input_path.clone()),
+                line: None,
                 message: format!("{}: skipping build - output is cached", proc.description()),
                 context: vec![],
             }))
@@ -473,6 +605,8 @@ pub async fn process_inputs<'a, P: TexProcessor, B: MessageBus + 'static>(
 
             Err(WorkerError::General(e)) => {
                 n_failures += 1;
+                gave_up = true;
+                failures.fail(&input_path);
                 bus.error(
                     Some(input_path.clone()),
                     "giving up early".to_owned(),
@@ -488,16 +622,18 @@ pub async fn process_inputs<'a, P: TexProcessor, B: MessageBus + 'static>(
                 // something is messed up that will break all of the
                 // builds.
                 n_failures += 1;
+                failures.fail(&input_path);
                 bus.error(
                     Some(input_path.clone()),
-                    "giving up early".to_owned(),
+                    "failed to prepare the TeX job".to_owned(),
                     Some(e),
                 )
                 .await;
 
                 // By `continue`-ing here, we are discarding the opinfo and
                 // not including this input in any subsequent processing.
-                // That's OK since we'll abandon the build after this pass.
+                // That's OK since we'll either abandon the build after this
+                // pass, or leave this input out of the rest of it.
                 continue;
             }
         };
@@ -508,7 +644,8 @@ pub async fn process_inputs<'a, P: TexProcessor, B: MessageBus + 'static>(
         let ip = input_path.clone();
 
         pool.spawn(async move {
-            tx.send(process_one_input(driver, ip, sp, bc, false).await)
+            let result = process_one_input(driver, ip.clone(), sp, bc, false).await;
+            tx.send((ip, result))
                 .await
                 .expect("channel waits for pool result");
         })
@@ -519,14 +656,17 @@ pub async fn process_inputs<'a, P: TexProcessor, B: MessageBus + 'static>(
         // Deal with results as we're doing the walk, if there are any.
 
         match rx.try_recv() {
-            Ok(result) => {
+            Ok((result_path, result)) => {
                 let tup = match result {
                     Ok(tup) => tup,
 
                     Err(WorkerError::General(_)) => {
                         n_failures += 1;
+                        gave_up = true;
+                        failures.fail(&result_path);
                         bus.post(Message::Error(AlertMessage {
-                            file: Some(input_path.clone()),
+                            file: Some(result_path),
+                            line: None,
                             message: "giving up early".into(),
                             context: Default::default(),
                         }))
@@ -540,6 +680,7 @@ pub async fn process_inputs<'a, P: TexProcessor, B: MessageBus + 'static>(
                         // something is messed up that will break all of the
                         // builds.
                         n_failures += 1;
+                        failures.fail(&result_path);
                         continue;
                     }
                 };
@@ -555,15 +696,18 @@ pub async fn process_inputs<'a, P: TexProcessor, B: MessageBus + 'static>(
 
     drop(tx);
 
-    while let Some(result) = rx.recv().await {
+    while let Some((result_path, result)) = rx.recv().await {
         let tup = match result {
             Ok(tup) => tup,
 
-            Err(_) => {
+            Err(e) => {
                 // At this point, we've already launched everything, so we can't
                 // give up early anymore; and the child process or inner callback
-                // should have displayed the error.
+                // should have displayed the error. But a general error should
+                // still stop the build, even in "keep going" mode.
                 n_failures += 1;
+                gave_up |= matches!(e, WorkerError::General(_));
+                failures.fail(&result_path);
                 continue;
             }
         };
@@ -574,7 +718,7 @@ pub async fn process_inputs<'a, P: TexProcessor, B: MessageBus + 'static>(
     // OK, all done!
 
     ensure!(
-        n_failures == 0,
+        n_failures == 0 || (failures.keep_going() && !gave_up),
         "{} out of {} build inputs failed",
         n_failures,
         n_tasks
@@ -607,7 +751,7 @@ async fn process_input_finish<P: TexProcessor, B: MessageBus>(
 
 /// This will only work with pass1 right now, since we haven't bothered to
 /// implement --debug for the pass2 subprocess.
-pub async fn debug_one_input<'a, P: TexProcessor, B: MessageBus + 'static>(
+pub async fn debug_one_input<P: TexProcessor, B: MessageBus + 'static>(
     input: RuntimeEntityIdent,
     proc: &mut P,
     cache: &mut Cache,
@@ -30,6 +30,7 @@ impl StatusBackend for WorkerStatusBackend {
     fn report(&mut self, kind: MessageKind, args: std::fmt::Arguments<'_>, err: Option<&Error>) {
         let mut alert = AlertMessage {
             file: Some(self.context.clone()),
+            line: None,
             message: format!("{}", args),
             context: Default::default(),
         };
@@ -53,6 +54,7 @@ impl StatusBackend for WorkerStatusBackend {
         // TODO: might actually want/need to handle this for real?
         self.post(Message::Error(AlertMessage {
             file: None,
+            line: None,
             message: "(internal error: TeX error log should not get here)".into(),
             context: Default::default(),
         }));
@@ -10,8 +10,10 @@ export enum ToolKind {
 
 export interface BuildSpecificSettings {
   indexUrl: string;
+  indicesUrl: string;
 }
 
 export const buildSpecificSettings: BuildSpecificSettings = {
-  indexUrl: ""
+  indexUrl: "",
+  indicesUrl: ""
 };
@@ -66,6 +66,7 @@ class DocLoader {
         this.title = "";
         this.content = "";
         this.content_tag_depth = 0;
+        this.is_redirect = false;
 
         const self = this;
 
@@ -73,6 +74,11 @@ class DocLoader {
             onopentag(name, attributes) {
                 self.tag_depth += 1;
 
+                if (name == "meta" && attributes["http-equiv"] == "refresh") {
+                    // Redirect pages for aliased entries have no content
+                    self.is_redirect = true;
+                }
+
                 if (self.state == "ignoring") {
                     if (name == "h1" && attributes.id == "title") {
                         self.state = "title";
@@ -125,6 +131,11 @@ class DocLoader {
         s.on('end', () => {
             this.parser.end();
 
+            if (this.is_redirect) {
+                this.manager.finish_task(null);
+                return;
+            }
+
             if (this.title == "") {
                 console.log(`error: ${fspath}: no title extracted`);
                 process.exit(1);